@@ -0,0 +1 @@
+CREATE INDEX factoids_author ON factoids (author)
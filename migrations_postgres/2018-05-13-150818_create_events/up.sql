@@ -0,0 +1,8 @@
+CREATE TABLE events (
+    id BIGSERIAL PRIMARY KEY,
+    receiver VARCHAR(32) NOT NULL,
+    content TEXT NOT NULL,
+    author VARCHAR(32) NOT NULL,
+    time TIMESTAMP NOT NULL,
+    "repeat" BIGINT NULL CHECK ("repeat" >= 0)
+)
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE counts (
+    name VARCHAR(32) PRIMARY KEY,
+    "count" BIGINT NOT NULL
+)
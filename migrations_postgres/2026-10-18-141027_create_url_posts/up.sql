@@ -0,0 +1,9 @@
+CREATE TABLE url_posts (
+    id BIGSERIAL PRIMARY KEY,
+    network VARCHAR(64) NOT NULL,
+    channel VARCHAR(64) NOT NULL,
+    nick VARCHAR(32) NOT NULL,
+    url TEXT NOT NULL,
+    title VARCHAR(512),
+    time TIMESTAMP NOT NULL
+)
@@ -0,0 +1 @@
+ALTER TABLE factoids ADD COLUMN locked BOOLEAN NOT NULL DEFAULT FALSE
@@ -0,0 +1,6 @@
+CREATE TABLE factoid_store (
+    factoid VARCHAR(32) NOT NULL,
+    store_key VARCHAR(64) NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (factoid, store_key)
+)
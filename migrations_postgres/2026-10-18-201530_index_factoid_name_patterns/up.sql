@@ -0,0 +1 @@
+CREATE INDEX factoids_name_pattern ON factoids (name text_pattern_ops)
@@ -0,0 +1,8 @@
+CREATE TABLE events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    receiver VARCHAR(32) NOT NULL,
+    content TEXT NOT NULL,
+    author VARCHAR(32) NOT NULL,
+    time TIMESTAMP NOT NULL,
+    "repeat" BIGINT NULL
+)
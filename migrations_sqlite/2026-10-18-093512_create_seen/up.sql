@@ -0,0 +1,10 @@
+CREATE TABLE seen (
+    network VARCHAR(64) NOT NULL,
+    nick VARCHAR(32) NOT NULL,
+    name VARCHAR(32) NOT NULL,
+    activity VARCHAR(16) NOT NULL,
+    channel VARCHAR(64),
+    content VARCHAR(512),
+    time TIMESTAMP NOT NULL,
+    PRIMARY KEY (network, nick)
+)
@@ -0,0 +1,311 @@
+//! Export and import of the data saved by plugins.
+//!
+//! The data is moved through a [`Backup`](struct.Backup.html) which does not
+//! depend on the storage backend, so it can be used to switch backends.
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::plugins::counter::database::Database as CounterDatabase;
+use crate::plugins::factoid::database::{
+    self as factoid, Database as FactoidDatabase, StoreDatabase as FactoidStoreDatabase,
+};
+use crate::plugins::quote::database::{self as quote, Database as QuoteDatabase};
+use crate::plugins::remind::database::{self as remind, Database as RemindDatabase};
+use crate::plugins::seen::database::{self as seen, Database as SeenDatabase};
+use crate::plugins::tell::database::{self as tell, Database as TellDatabase};
+use crate::plugins::url::database::{self as url, Database as UrlDatabase};
+
+use self::error::*;
+use failure::ResultExt;
+
+/// The version of the format written by [`export()`](fn.export.html).
+pub const VERSION: u32 = 1;
+
+/// All data saved by the plugins.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    pub factoids: Vec<factoid::Factoid>,
+    /// Missing in backups made before Lua factoids could store values.
+    #[serde(default)]
+    pub factoid_store: Vec<factoid::StoredValue>,
+    pub quotes: Vec<quote::Quote>,
+    pub tells: Vec<tell::TellMessage>,
+    pub events: Vec<remind::Event>,
+    pub counts: BTreeMap<String, i64>,
+    /// Missing in backups made before the seen plugin existed.
+    #[serde(default)]
+    pub seen: Vec<seen::Seen>,
+    /// Missing in backups made before the URL history existed.
+    #[serde(default)]
+    pub urls: Vec<url::Post>,
+}
+
+/// Collects the data of every plugin.
+#[allow(clippy::too_many_arguments)]
+pub fn export(
+    factoids: &dyn FactoidDatabase,
+    factoid_store: &dyn FactoidStoreDatabase,
+    quotes: &dyn QuoteDatabase,
+    tells: &dyn TellDatabase,
+    events: &dyn RemindDatabase,
+    counts: &dyn CounterDatabase,
+    seen: &dyn SeenDatabase,
+    urls: &dyn UrlDatabase,
+) -> Result<Backup, BackupError> {
+    let mut factoids = factoids.list_factoids().context(ErrorKind::Factoid)?;
+    factoids.sort_by(|a, b| (&a.name, a.idx).cmp(&(&b.name, b.idx)));
+
+    let mut factoid_store = factoid_store.list_values().context(ErrorKind::Factoid)?;
+    factoid_store.sort_by(|a, b| (&a.factoid, &a.store_key).cmp(&(&b.factoid, &b.store_key)));
+
+    let mut quotes = quotes.list_quotes().context(ErrorKind::Quote)?;
+    quotes.sort_by(|a, b| (&a.channel, &a.quotee, a.idx).cmp(&(&b.channel, &b.quotee, b.idx)));
+
+    let mut tells = tells.list_tells().context(ErrorKind::Tell)?;
+    tells.sort_by_key(|t| t.time);
+
+    let mut events = events.list_events().context(ErrorKind::Remind)?;
+    events.sort_by_key(|e| e.id);
+
+    let counts = counts
+        .list_counts()
+        .context(ErrorKind::Counter)?
+        .into_iter()
+        .collect();
+
+    let mut seen = seen.list_seen().context(ErrorKind::Seen)?;
+    seen.sort_by(|a, b| (&a.network, &a.nick).cmp(&(&b.network, &b.nick)));
+
+    let mut urls = urls.list_posts().context(ErrorKind::Url)?;
+    urls.sort_by_key(|p| p.id);
+
+    Ok(Backup {
+        version: VERSION,
+        factoids,
+        factoid_store,
+        quotes,
+        tells,
+        events,
+        counts,
+        seen,
+        urls,
+    })
+}
+
+/// Adds everything from `backup` to the databases.
+///
+/// Nothing is written if a factoid or quote of the backup exists already.
+/// Tells, reminders and posted URLs get new ids, the ones which exist
+/// with the same content are skipped so an import can be repeated.
+#[allow(clippy::too_many_arguments)]
+pub fn import(
+    backup: &Backup,
+    factoids: &mut dyn FactoidDatabase,
+    factoid_store: &mut dyn FactoidStoreDatabase,
+    quotes: &mut dyn QuoteDatabase,
+    tells: &mut dyn TellDatabase,
+    events: &mut dyn RemindDatabase,
+    counts: &mut dyn CounterDatabase,
+    seen: &mut dyn SeenDatabase,
+    urls: &mut dyn UrlDatabase,
+) -> Result<(), BackupError> {
+    if backup.version > VERSION {
+        Err(ErrorKind::UnsupportedVersion)?;
+    }
+
+    let existing = factoids.list_factoids().context(ErrorKind::Factoid)?;
+    let existing = existing
+        .iter()
+        .map(|f| (&f.name, f.idx))
+        .collect::<HashSet<_>>();
+    if backup
+        .factoids
+        .iter()
+        .any(|f| existing.contains(&(&f.name, f.idx)))
+    {
+        Err(ErrorKind::Conflict)?;
+    }
+
+    let existing = quotes.list_quotes().context(ErrorKind::Quote)?;
+    let existing = existing
+        .iter()
+        .map(|q| (&q.quotee, &q.channel, q.idx))
+        .collect::<HashSet<_>>();
+    if backup
+        .quotes
+        .iter()
+        .any(|q| existing.contains(&(&q.quotee, &q.channel, q.idx)))
+    {
+        Err(ErrorKind::Conflict)?;
+    }
+
+    for f in &backup.factoids {
+        let factoid = factoid::NewFactoid {
+            name: &f.name,
+            idx: f.idx,
+            content: &f.content,
+            author: &f.author,
+            created: f.created,
+            locked: f.locked,
+        };
+
+        factoids
+            .insert_factoid(&factoid)
+            .context(ErrorKind::Factoid)?;
+    }
+
+    for v in &backup.factoid_store {
+        let value = factoid::NewStoredValue {
+            factoid: &v.factoid,
+            store_key: &v.store_key,
+            value: &v.value,
+        };
+
+        factoid_store
+            .set_value(&value)
+            .context(ErrorKind::Factoid)?;
+    }
+
+    for q in &backup.quotes {
+        let quote = quote::NewQuote {
+            quotee: &q.quotee,
+            channel: &q.channel,
+            idx: q.idx,
+            content: &q.content,
+            author: &q.author,
+            created: q.created,
+        };
+
+        quotes.insert_quote(&quote).context(ErrorKind::Quote)?;
+    }
+
+    let existing = tells.list_tells().context(ErrorKind::Tell)?;
+    let existing = existing
+        .iter()
+        .map(|t| (&t.sender, &t.receiver, t.time, &t.message))
+        .collect::<HashSet<_>>();
+    for t in &backup.tells {
+        if existing.contains(&(&t.sender, &t.receiver, t.time, &t.message)) {
+            continue;
+        }
+
+        let tell = tell::NewTellMessage {
+            sender: &t.sender,
+            receiver: &t.receiver,
+            time: t.time,
+            message: &t.message,
+        };
+
+        tells.insert_tell(&tell).context(ErrorKind::Tell)?;
+    }
+
+    let existing = events.list_events().context(ErrorKind::Remind)?;
+    let existing = existing
+        .iter()
+        .map(|e| (&e.receiver, &e.content, &e.author, e.time))
+        .collect::<HashSet<_>>();
+    for e in &backup.events {
+        if existing.contains(&(&e.receiver, &e.content, &e.author, e.time)) {
+            continue;
+        }
+
+        let event = remind::NewEvent {
+            receiver: &e.receiver,
+            content: &e.content,
+            author: &e.author,
+            time: &e.time,
+            repeat: e.repeat,
+        };
+
+        events.insert_event(&event).context(ErrorKind::Remind)?;
+    }
+
+    for (name, count) in &backup.counts {
+        counts.set_count(name, *count).context(ErrorKind::Counter)?;
+    }
+
+    for s in &backup.seen {
+        let new = seen::NewSeen {
+            network: &s.network,
+            nick: &s.nick,
+            name: &s.name,
+            activity: &s.activity,
+            channel: s.channel.as_ref().map(|c| &c[..]),
+            content: s.content.as_ref().map(|c| &c[..]),
+            time: s.time,
+        };
+
+        seen.insert_seen(&new).context(ErrorKind::Seen)?;
+    }
+
+    let existing = urls.list_posts().context(ErrorKind::Url)?;
+    let existing = existing
+        .iter()
+        .map(|p| (&p.network, &p.channel, &p.nick, &p.url, p.time))
+        .collect::<HashSet<_>>();
+    for p in &backup.urls {
+        if existing.contains(&(&p.network, &p.channel, &p.nick, &p.url, p.time)) {
+            continue;
+        }
+
+        let post = url::NewPost {
+            network: &p.network,
+            channel: &p.channel,
+            nick: &p.nick,
+            url: &p.url,
+            title: p.title.as_ref().map(|t| &t[..]),
+            time: p.time,
+        };
+
+        urls.insert_post(&post).context(ErrorKind::Url)?;
+    }
+
+    Ok(())
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "BackupError"]
+    pub enum ErrorKind {
+        /// Factoid error
+        #[fail(display = "Failed to transfer factoids")]
+        Factoid,
+
+        /// Quote error
+        #[fail(display = "Failed to transfer quotes")]
+        Quote,
+
+        /// Tell error
+        #[fail(display = "Failed to transfer tells")]
+        Tell,
+
+        /// Remind error
+        #[fail(display = "Failed to transfer reminders")]
+        Remind,
+
+        /// Counter error
+        #[fail(display = "Failed to transfer counts")]
+        Counter,
+
+        /// Seen error
+        #[fail(display = "Failed to transfer seen nicks")]
+        Seen,
+
+        /// URL error
+        #[fail(display = "Failed to transfer posted URLs")]
+        Url,
+
+        /// Conflict error
+        #[fail(display = "The backup contains factoids or quotes which exist already")]
+        Conflict,
+
+        /// Unsupported version error
+        #[fail(display = "The backup was made by a newer version of frippy")]
+        UnsupportedVersion,
+    }
+}
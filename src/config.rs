@@ -0,0 +1,282 @@
+//! Validation of the frippy specific parts of a config file.
+//!
+//! The irc crate only checks the syntax of a config file,
+//! so [`validate()`](fn.validate.html) is used to find mistakes in the
+//! values before they cause problems at runtime.
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use failure::Error;
+use regex::Regex;
+use reqwest::header::HeaderValue;
+use reqwest::Proxy;
+use serde::Deserialize;
+
+use crate::utils::Policy;
+use crate::Config;
+
+/// A mistake found in a config file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// The key which has an invalid value.
+    pub key: String,
+    /// What is wrong with the value.
+    pub message: String,
+}
+
+impl Problem {
+    fn new<K: Into<String>, M: Into<String>>(key: K, message: M) -> Self {
+        Problem {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// The `[http]` section which configures the client used for downloads.
+///
+/// The irc crate ignores unknown sections, so it is read separately.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub user_agent: Option<String>,
+    /// A proxy URL like `http://127.0.0.1:3128`, used for every scheme.
+    pub proxy: Option<String>,
+    /// A duration like `10s` which limits a whole request.
+    pub timeout: Option<String>,
+    pub accept_language: Option<String>,
+    pub max_redirects: Option<usize>,
+    /// Enables gzip compression, which is the default.
+    pub gzip: Option<bool>,
+}
+
+impl HttpConfig {
+    /// Reads the `[http]` section of the config file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct File {
+            #[serde(default)]
+            http: HttpConfig,
+        }
+
+        let content = fs::read_to_string(path)?;
+        Ok(toml::from_str::<File>(&content)?.http)
+    }
+}
+
+/// Checks the values of the `[http]` section.
+pub fn validate_http(http: &HttpConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if let Some(ref user_agent) = http.user_agent {
+        if HeaderValue::from_str(user_agent).is_err() {
+            problems.push(Problem::new(
+                "http.user_agent",
+                "contains invalid characters",
+            ));
+        }
+    }
+
+    if let Some(ref accept_language) = http.accept_language {
+        if HeaderValue::from_str(accept_language).is_err() {
+            problems.push(Problem::new(
+                "http.accept_language",
+                "contains invalid characters",
+            ));
+        }
+    }
+
+    if let Some(ref proxy) = http.proxy {
+        if let Err(e) = Proxy::all(proxy.as_str()) {
+            problems.push(Problem::new("http.proxy", format!("invalid proxy: {}", e)));
+        }
+    }
+
+    if let Some(ref timeout) = http.timeout {
+        if let Err(e) = humantime::parse_duration(timeout) {
+            problems.push(Problem::new(
+                "http.timeout",
+                format!("invalid duration: {}", e),
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Checks all values used by frippy and returns every problem found.
+///
+/// `plugins` contains the names of the plugins which can be disabled.
+pub fn validate(config: &Config, plugins: &[&str]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if let Some(ref channels) = config.channels {
+        for channel in channels {
+            if let Some(message) = check_channel(channel) {
+                problems.push(Problem::new("channels", message));
+            }
+        }
+    }
+
+    if let Some(ref keys) = config.channel_keys {
+        for channel in keys.keys() {
+            if let Some(message) = check_channel(channel) {
+                problems.push(Problem::new(format!("channel_keys.{}", channel), message));
+            }
+        }
+    }
+
+    if let Some(ref options) = config.options {
+        let mut keys = options.keys().collect::<Vec<_>>();
+        keys.sort();
+
+        for key in keys {
+            if let Some(message) = check_option(key, &options[key], plugins) {
+                problems.push(Problem::new(format!("options.{}", key), message));
+            }
+        }
+
+        let storage = ["database_url", "mysql_url", "sqlite_path", "data_dir"];
+        let mut configured = storage.iter().filter(|k| options.contains_key(**k));
+        if let Some(used) = configured.next() {
+            for ignored in configured {
+                problems.push(Problem::new(
+                    format!("options.{}", ignored),
+                    format!("ignored because {} is set", used),
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+fn check_option(key: &str, value: &str, plugins: &[&str]) -> Option<String> {
+    match key {
+        "prefix" => {
+            if value.is_empty() {
+                Some(String::from("must not be empty"))
+            } else if value.chars().any(char::is_whitespace) {
+                Some(String::from("must not contain whitespace"))
+            } else {
+                None
+            }
+        }
+        "disabled_plugins" => {
+            let unknown = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !plugins.iter().any(|p| p.eq_ignore_ascii_case(name)))
+                .map(|name| format!("\"{}\"", name))
+                .collect::<Vec<_>>();
+
+            if unknown.is_empty() {
+                None
+            } else {
+                Some(format!(
+                    "unknown plugins {}, available are {}",
+                    unknown.join(", "),
+                    plugins.join(", ")
+                ))
+            }
+        }
+        "database_url" | "mysql_url" => check_database_url(value),
+        "sqlite_path" if !cfg!(feature = "sqlite") => {
+            Some(String::from("frippy was not built with the sqlite feature"))
+        }
+        "log_retention" => match humantime::parse_duration(value) {
+            Ok(_) => None,
+            Err(e) => Some(format!("invalid duration: {}", e)),
+        },
+        "sqlite_path" | "data_dir" | "log_dir" | "bridge_name" => {
+            if value.is_empty() {
+                Some(String::from("must not be empty"))
+            } else {
+                None
+            }
+        }
+        "bridge_relay_format" => check_regex(value, &["username"]),
+        "bridge_regex" => check_regex(value, &["message"]),
+        "bridge_ignore_regex" => check_regex(value, &[]),
+        "bridge_remove_zws" => match value.to_lowercase().as_str() {
+            "true" | "false" => None,
+            _ => Some(String::from("must be either true or false")),
+        },
+        "fetch_allowlist" => Policy::with_allowlist(value).err(),
+        "url_max_per_message" => match value.parse::<usize>() {
+            Ok(_) => None,
+            Err(_) => Some(String::from("must be a positive number")),
+        },
+        "url_allow_domains" | "url_deny_domains" | "url_ignore_nicks" | "url_shorteners" => None,
+        "factoid_protected_names" => None,
+        key if key.starts_with("url_template:") => {
+            if key.len() == "url_template:".len() {
+                Some(String::from("is missing the domain"))
+            } else if value.is_empty() {
+                Some(String::from("must not be empty"))
+            } else {
+                None
+            }
+        }
+        key if key.starts_with("url_allow_domains:") || key.starts_with("url_deny_domains:") => {
+            check_channel(&key[key.find(':').unwrap() + 1..])
+        }
+        _ => Some(String::from("unknown option")),
+    }
+}
+
+fn check_channel(channel: &str) -> Option<String> {
+    if !channel.starts_with(|c| "#&+!".contains(c)) {
+        Some(format!("\"{}\" does not start with #, &, + or !", channel))
+    } else if channel.len() < 2 {
+        Some(format!("\"{}\" is missing a name", channel))
+    } else if channel.contains(|c| c == ' ' || c == ',' || c == '\x07') {
+        Some(format!(
+            "\"{}\" contains a space, comma or bell character",
+            channel
+        ))
+    } else {
+        None
+    }
+}
+
+fn check_database_url(url: &str) -> Option<String> {
+    let idx = match url.find("://") {
+        Some(idx) => idx,
+        None => return Some(String::from("is missing a scheme like mysql://")),
+    };
+
+    let (feature, built) = match &url[..idx] {
+        "mysql" => ("mysql", cfg!(feature = "mysql")),
+        "postgres" | "postgresql" => ("postgres", cfg!(feature = "postgres")),
+        "sqlite" => ("sqlite", cfg!(feature = "sqlite")),
+        scheme => return Some(format!("unsupported scheme \"{}\"", scheme)),
+    };
+
+    if !built {
+        Some(format!("frippy was not built with the {} feature", feature))
+    } else if url[idx + 3..].is_empty() {
+        Some(String::from("is missing the database"))
+    } else {
+        None
+    }
+}
+
+fn check_regex(re: &str, groups: &[&str]) -> Option<String> {
+    let re = match Regex::new(re) {
+        Ok(re) => re,
+        Err(e) => return Some(format!("invalid regex: {}", e)),
+    };
+
+    groups
+        .iter()
+        .find(|g| !re.capture_names().any(|n| n == Some(**g)))
+        .map(|g| format!("is missing the named group (?P<{}>...)", g))
+}
@@ -48,4 +48,12 @@ pub enum ErrorKind {
     /// A Counter error
     #[fail(display = "A Counter error has occured")]
     Counter,
+
+    /// A Seen error
+    #[fail(display = "A Seen error has occured")]
+    Seen,
+
+    /// A Log error
+    #[fail(display = "A Log error has occured")]
+    Log,
 }
@@ -28,13 +28,16 @@
 //! Frippy uses the [log](https://docs.rs/log) crate so you can log events
 //! which might be of interest.
 
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 #[macro_use]
 extern crate diesel;
 
+pub mod backup;
+pub mod config;
 pub mod error;
 pub mod plugin;
 pub mod plugins;
+pub mod storage;
 pub mod utils;
 
 use crate::plugin::*;
@@ -58,6 +61,34 @@ use std::thread;
 
 pub trait FrippyClient: Client + Send + Sync + Clone + fmt::Debug {
     fn current_nickname(&self) -> &str;
+
+    /// Checks if `nick` is listed in the owners of the config.
+    fn is_owner(&self, nick: &str) -> bool {
+        self.config().owners.as_ref().map_or(false, |owners| {
+            owners.iter().any(|o| o.eq_ignore_ascii_case(nick))
+        })
+    }
+
+    /// Checks if the `nick!user@host` `prefix` matches one of the owners
+    /// which are given as a hostmask like `nick!*@host`.
+    ///
+    /// Unlike `is_owner` the nick alone is not enough,
+    /// anyone can take it while the owner is offline.
+    fn is_owner_mask(&self, prefix: &str) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        };
+
+        self.config().owners.as_ref().map_or(false, |owners| {
+            owners
+                .iter()
+                .filter(|o| o.contains('!') && o.contains('@'))
+                .filter_map(|o| glob::Pattern::new(o).ok())
+                .any(|o| o.matches_with(prefix, options))
+        })
+    }
 }
 
 impl FrippyClient for IrcClient {
@@ -126,6 +157,27 @@ impl<'a> Bot<'a> {
         self.plugins.remove(name)
     }
 
+    /// Returns the names of all added plugins in lowercase.
+    ///
+    /// # Examples
+    /// ```
+    /// use frippy::{plugins, Bot};
+    ///
+    /// let mut bot = frippy::Bot::new(".");
+    /// bot.add_plugin(plugins::help::Help::new());
+    /// assert_eq!(bot.plugin_names(), vec!["help"]);
+    /// ```
+    pub fn plugin_names(&self) -> Vec<&str> {
+        let mut names = self
+            .plugins
+            .plugins
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
     /// This connects the `Bot` to IRC and creates a task on the
     /// [`IrcReactor`](../irc/client/reactor/struct.IrcReactor.html)
     /// which returns an Ok if the connection was cleanly closed and
@@ -165,50 +217,79 @@ impl<'a> Bot<'a> {
 
         let mut plugins = self.plugins.clone();
         let prefix = self.prefix.to_owned();
+        let bridge = config.options.as_ref().map(Bridge::new).unwrap_or_default();
 
         reactor.register_client_with_handler(client, move |client, message| {
-            process_msg(client, &mut plugins, &prefix, message)
+            process_msg(client, &mut plugins, &prefix, &bridge, message)
         });
 
         Ok(())
     }
 }
 
+/// The bridge options of a config with their regexes compiled.
+#[derive(Debug, Default)]
+struct Bridge {
+    name: Option<String>,
+    relay_format: Option<Regex>,
+    ignore: Option<Regex>,
+    regex: Option<Regex>,
+    remove_zws: bool,
+}
+
+impl Bridge {
+    fn new(options: &HashMap<String, String>) -> Self {
+        // Invalid regexes are reported by config::validate before connecting
+        let regex = |key: &str| {
+            options.get(key).and_then(|re| match Regex::new(re) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    error!("Ignoring {}: {}", key, e);
+                    None
+                }
+            })
+        };
+
+        Bridge {
+            name: options.get("bridge_name").cloned(),
+            relay_format: regex("bridge_relay_format"),
+            ignore: regex("bridge_ignore_regex"),
+            regex: regex("bridge_regex"),
+            remove_zws: options
+                .get("bridge_remove_zws")
+                .map_or(false, |s| s.eq_ignore_ascii_case("true")),
+        }
+    }
+}
+
 fn process_msg<C>(
     client: &C,
     plugins: &mut ThreadedPlugins<C>,
     prefix: &str,
+    bridge: &Bridge,
     mut message: Message,
 ) -> Result<(), IrcError>
 where
     C: FrippyClient + 'static,
 {
-    if let (Command::PRIVMSG(target, content), Some(options)) =
-        (&message.command, &client.config().options)
-    {
+    if let Command::PRIVMSG(ref target, ref content) = message.command {
         let nick = message.source_nickname().unwrap().to_owned();
         let (mut bridge_user, mut bridge_message) = (None, None);
-        if let Some(bridge_re) = options.get("bridge_relay_format") {
-            // FIXME store regex and remove unwrap
-            let re = Regex::new(bridge_re).unwrap();
+        if let Some(ref re) = bridge.relay_format {
             if let Some(caps) = re.captures(&nick) {
                 bridge_user = caps.name("username").map(|c| c.as_str().to_owned());
             }
         }
 
-        if bridge_user.is_some() || Some(&nick) == options.get("bridge_name") {
-            if let Some(ignore) = options.get("bridge_ignore_regex") {
-                // FIXME store regex and remove unwrap
-                let re = Regex::new(ignore).unwrap();
-                if re.is_match(&content) {
+        if bridge_user.is_some() || Some(&nick) == bridge.name.as_ref() {
+            if let Some(ref re) = bridge.ignore {
+                if re.is_match(content) {
                     return Ok(());
                 }
             }
 
-            if let Some(re_str) = options.get("bridge_regex") {
-                // FIXME store regex and remove unwrap
-                let re = Regex::new(re_str).unwrap();
-                if let Some(caps) = re.captures(&content) {
+            if let Some(ref re) = bridge.regex {
+                if let Some(caps) = re.captures(content) {
                     if bridge_user.is_none() {
                         bridge_user = caps.name("username").map(|c| c.as_str().to_owned());
                     }
@@ -218,12 +299,7 @@ where
         }
 
         if let Some(mut bridge_user) = bridge_user {
-            if Some("true")
-                == options
-                    .get("bridge_remove_zws")
-                    .map(|s| s.to_lowercase())
-                    .as_deref()
-            {
+            if bridge.remove_zws {
                 bridge_user = bridge_user.replace("\u{200b}", "");
             }
             message.prefix = message.prefix.map(|s| s.replace(&nick, &bridge_user));
@@ -1,146 +1,487 @@
 use std::collections::HashMap;
-#[cfg(feature = "mysql")]
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+#[cfg(feature = "sql")]
 use std::sync::Arc;
 
 use glob::glob;
 use irc::client::reactor::IrcReactor;
+use irc::client::IrcClient;
 
+use frippy::config::HttpConfig;
 use frippy::plugins::counter::Counter;
 use frippy::plugins::factoid::Factoid;
 use frippy::plugins::help::Help;
 use frippy::plugins::keepnick::KeepNick;
+use frippy::plugins::log::Log;
 use frippy::plugins::quote::Quote;
 use frippy::plugins::remind::Remind;
 use frippy::plugins::sed::Sed;
+use frippy::plugins::seen::Seen;
 use frippy::plugins::tell::Tell;
 use frippy::plugins::unicode::Unicode;
+use frippy::plugins::url::database::{PostFile, PostMap};
 use frippy::plugins::url::UrlTitles;
+use frippy::storage::FileDatabase;
+use frippy::utils::{Http, Policy};
+use frippy::{backup, config};
 
-use failure::{bail, Error};
-use frippy::Config;
-use log::{error, info};
+use frippy::plugins::factoid::database::{Factoid as FactoidEntry, StoredValue};
+use frippy::plugins::quote::database::Quote as QuoteEntry;
+use frippy::plugins::remind::database::Event;
+use frippy::plugins::seen::database::Seen as SeenEntry;
+use frippy::plugins::tell::database::TellMessage;
 
-#[cfg(feature = "mysql")]
+use frippy::plugins::counter::database::Database as CounterDatabase;
+use frippy::plugins::factoid::database::Database as FactoidDatabase;
+use frippy::plugins::factoid::database::StoreDatabase as FactoidStoreDatabase;
+use frippy::plugins::quote::database::Database as QuoteDatabase;
+use frippy::plugins::remind::database::Database as RemindDatabase;
+use frippy::plugins::seen::database::Database as SeenDatabase;
+use frippy::plugins::tell::database::Database as TellDatabase;
+use frippy::plugins::url::database::Database as UrlDatabase;
+
+#[cfg(feature = "sql")]
+use r2d2::Pool;
+#[cfg(feature = "sql")]
+use r2d2_diesel::ConnectionManager;
+
+use clap::{
+    crate_description, crate_version, App, Arg, ArgMatches, ErrorKind as ClapErrorKind, SubCommand,
+};
+use failure::{bail, format_err, Error, ResultExt};
+use frippy::{Bot, Config};
+use log::{error, info, warn};
+
+#[cfg(feature = "sql")]
 #[macro_use]
 extern crate diesel_migrations;
 
 #[cfg(feature = "mysql")]
-embed_migrations!();
+mod mysql {
+    embed_migrations!("migrations");
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    embed_migrations!("migrations_sqlite");
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    embed_migrations!("migrations_postgres");
+}
+
+// Exit codes as defined in sysexits.h
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATAERR: i32 = 65;
+const EXIT_UNAVAILABLE: i32 = 69;
+const EXIT_CANTCREAT: i32 = 73;
+const EXIT_CONFIG: i32 = 78;
+
+/// An error which shuts frippy down with a specific exit code.
+struct Exit {
+    code: i32,
+    error: Error,
+}
+
+trait OrExit<T> {
+    fn or_exit(self, code: i32) -> Result<T, Exit>;
+}
+
+impl<T, E: Into<Error>> OrExit<T> for Result<T, E> {
+    fn or_exit(self, code: i32) -> Result<T, Exit> {
+        self.map_err(|e| Exit {
+            code,
+            error: e.into(),
+        })
+    }
+}
+
+/// The parsed command line arguments.
+struct Args {
+    config: PathBuf,
+    log_config: PathBuf,
+    data_dir: Option<PathBuf>,
+    check: bool,
+    strict: bool,
+    migrate_only: bool,
+    export: Option<PathBuf>,
+    import: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let matches = App::new("frippy")
+            .version(crate_version!())
+            .about(crate_description!())
+            .arg(
+                Arg::with_name("config")
+                    .short("c")
+                    .long("config")
+                    .value_name("PATH")
+                    .default_value("configs")
+                    .help("Config file or directory containing config files"),
+            )
+            .arg(
+                Arg::with_name("log-config")
+                    .long("log-config")
+                    .value_name("FILE")
+                    .default_value("log.yml")
+                    .help("log4rs config file"),
+            )
+            .arg(
+                Arg::with_name("data-dir")
+                    .long("data-dir")
+                    .value_name("DIR")
+                    .help(
+                        "Directory for plugin data of configs without a database, \
+                         each of them gets a subdirectory named after its file, \
+                         relative data_dir and sqlite_path options are resolved against it",
+                    ),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .long("check")
+                    .help("Validates the config files and exits"),
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .help("Refuses to start if a config file has any problems"),
+            )
+            .arg(
+                Arg::with_name("migrate-only")
+                    .long("migrate-only")
+                    .conflicts_with("check")
+                    .help("Runs the database migrations and exits"),
+            )
+            .subcommand(
+                SubCommand::with_name("export")
+                    .about("Writes all plugin data of a single config file to a JSON file")
+                    .arg(Arg::with_name("FILE").required(true)),
+            )
+            .subcommand(
+                SubCommand::with_name("import")
+                    .about("Adds all plugin data from a JSON file to the storage of a single config file")
+                    .arg(Arg::with_name("FILE").required(true)),
+            )
+            .get_matches_safe()
+            .unwrap_or_else(|e| match e.kind {
+                ClapErrorKind::HelpDisplayed | ClapErrorKind::VersionDisplayed => e.exit(),
+                _ => {
+                    eprintln!("{}", e.message);
+                    process::exit(EXIT_USAGE);
+                }
+            });
+
+        let path = |m: &ArgMatches, name| m.value_of(name).map(PathBuf::from);
+
+        Args {
+            config: path(&matches, "config").expect("config has a default value"),
+            log_config: path(&matches, "log-config").expect("log-config has a default value"),
+            data_dir: path(&matches, "data-dir"),
+            check: matches.is_present("check"),
+            strict: matches.is_present("strict"),
+            migrate_only: matches.is_present("migrate-only"),
+            export: matches
+                .subcommand_matches("export")
+                .and_then(|m| path(m, "FILE")),
+            import: matches
+                .subcommand_matches("import")
+                .and_then(|m| path(m, "FILE")),
+        }
+    }
+}
 
 fn main() {
-    if let Err(e) = log4rs::init_file("log.yml", Default::default()) {
+    let args = Args::parse();
+
+    if let Err(e) = log4rs::init_file(&args.log_config, Default::default()) {
         use log4rs::Error;
         match e {
             Error::Log(e) => eprintln!("Log4rs error: {}", e),
-            Error::Log4rs(e) => eprintln!("Failed to parse \"log.yml\" as log4rs config: {}", e),
+            Error::Log4rs(e) => eprintln!(
+                "Failed to parse {:?} as log4rs config: {}",
+                args.log_config, e
+            ),
         }
 
-        return;
+        process::exit(EXIT_CONFIG);
     }
 
+    let result = if args.check {
+        check(&args)
+    } else if args.migrate_only {
+        migrate(&args)
+    } else if let Some(ref path) = args.export {
+        export(&args, path)
+    } else if let Some(ref path) = args.import {
+        import(&args, path)
+    } else {
+        run(&args)
+    };
+
     // Print any errors that caused frippy to shut down
-    if let Err(e) = run() {
-        let text = e
+    if let Err(Exit { code, error }) = result {
+        let text = error
             .iter_causes()
-            .fold(format!("{}", e), |acc, err| format!("{}: {}", acc, err));
+            .fold(format!("{}", error), |acc, err| format!("{}: {}", acc, err));
         error!("{}", text);
+
+        process::exit(code);
     }
 }
 
-fn run() -> Result<(), Error> {
-    // Load all toml files in the configs directory
+/// A config file and the `[http]` section which the irc crate ignores.
+struct BotConfig {
+    /// The file name without extension, it separates the data of
+    /// configs which would otherwise share the data directory.
+    name: String,
+    irc: Config,
+    http: HttpConfig,
+}
+
+/// Loads `path` or all toml files inside of it if it is a directory.
+///
+/// Files which fail to load or validate are skipped unless `strict` is set.
+fn load_configs(path: &Path, strict: bool) -> Result<Vec<BotConfig>, Exit> {
+    let paths = if path.is_dir() {
+        let pattern = path.join("*.toml");
+        glob(&pattern.to_string_lossy())
+            .or_exit(EXIT_CONFIG)?
+            .collect::<Result<Vec<_>, _>>()
+            .or_exit(EXIT_CONFIG)?
+    } else {
+        vec![path.to_owned()]
+    };
+
+    let bot = create_bot(
+        ".",
+        "",
+        Databases::hashmap(),
+        Log::new(None, None),
+        &Http::default(),
+    );
+    let plugins = bot.plugin_names();
+
+    // Report every problem before giving up
     let mut configs = Vec::new();
-    for toml in glob("configs/*.toml").unwrap() {
-        match toml {
-            Ok(path) => {
-                info!("Loading {}", path.to_str().unwrap());
-                match Config::load(path) {
-                    Ok(v) => configs.push(v),
-                    Err(e) => error!("Incorrect config file {}", e),
-                }
+    let mut invalid = 0;
+    for path in paths {
+        info!("Loading {}", path.display());
+        let config = match Config::load(&path) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Incorrect config file {}: {}", path.display(), e);
+                invalid += 1;
+                continue;
+            }
+        };
+
+        // The irc crate does not know about this section
+        let http = match HttpConfig::load(&path) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Incorrect [http] section in {}: {}", path.display(), e);
+                invalid += 1;
+                continue;
             }
-            Err(e) => error!("Failed to read path {}", e),
+        };
+
+        let mut problems = config::validate(&config, &plugins);
+        problems.extend(config::validate_http(&http));
+        for problem in &problems {
+            error!("{}: {}", path.display(), problem);
         }
+
+        let config = BotConfig {
+            name: path
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned()),
+            irc: config,
+            http,
+        };
+
+        if problems.is_empty() {
+            configs.push(config);
+        } else if strict {
+            invalid += 1;
+        } else {
+            warn!("Using {} despite its problems", path.display());
+            configs.push(config);
+        }
+    }
+
+    if strict && invalid > 0 {
+        return Err(format_err!("{} config files are invalid", invalid)).or_exit(EXIT_CONFIG);
     }
 
     // Without configs the bot would just idle
     if configs.is_empty() {
-        bail!("No config file was found");
+        return Err(format_err!(
+            "No config file was found in {}",
+            path.display()
+        ))
+        .or_exit(EXIT_CONFIG);
+    }
+
+    Ok(configs)
+}
+
+fn check(args: &Args) -> Result<(), Exit> {
+    let configs = load_configs(&args.config, true)?;
+    info!("{} config files are valid", configs.len());
+
+    Ok(())
+}
+
+fn migrate(args: &Args) -> Result<(), Exit> {
+    for config in load_configs(&args.config, true)? {
+        Databases::open(&config, args.data_dir.as_ref().map(PathBuf::as_path))
+            .or_exit(EXIT_UNAVAILABLE)?;
+    }
+
+    Ok(())
+}
+
+/// Loads the config whose storage is exported or imported.
+fn load_single_config(args: &Args) -> Result<BotConfig, Exit> {
+    let mut configs = load_configs(&args.config, true)?;
+    if configs.len() > 1 {
+        return Err(format_err!(
+            "{} contains {} config files, select one of them with --config",
+            args.config.display(),
+            configs.len()
+        ))
+        .or_exit(EXIT_USAGE);
+    }
+
+    Ok(configs.remove(0))
+}
+
+fn export(args: &Args, path: &Path) -> Result<(), Exit> {
+    let config = load_single_config(args)?;
+    let dbs = Databases::open(&config, args.data_dir.as_ref().map(PathBuf::as_path))
+        .or_exit(EXIT_UNAVAILABLE)?;
+    let backup = backup::export(
+        &*dbs.factoids,
+        &*dbs.factoid_store,
+        &*dbs.quotes,
+        &*dbs.tells,
+        &*dbs.events,
+        &*dbs.counts,
+        &*dbs.seen,
+        &*dbs.urls,
+    )
+    .or_exit(EXIT_FAILURE)?;
+
+    let file = File::create(path).or_exit(EXIT_CANTCREAT)?;
+    serde_json::to_writer_pretty(file, &backup).or_exit(EXIT_CANTCREAT)?;
+    info!(
+        "Exported {} factoids, {} stored values, {} quotes, {} tells, {} reminders, \
+         {} counts, {} seen nicks and {} posted URLs to {}",
+        backup.factoids.len(),
+        backup.factoid_store.len(),
+        backup.quotes.len(),
+        backup.tells.len(),
+        backup.events.len(),
+        backup.counts.len(),
+        backup.seen.len(),
+        backup.urls.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn import(args: &Args, path: &Path) -> Result<(), Exit> {
+    let file = File::open(path).or_exit(EXIT_DATAERR)?;
+    let backup: backup::Backup = serde_json::from_reader(file).or_exit(EXIT_DATAERR)?;
+    let config = load_single_config(args)?;
+    let data_dir = args.data_dir.as_ref().map(PathBuf::as_path);
+
+    // Writing the files after every entry would take quadratic time
+    if let Some(dir) = Databases::files_dir(&config, data_dir) {
+        let mut files = Files::open(&dir).or_exit(EXIT_UNAVAILABLE)?;
+        files.begin_batch();
+        backup::import(
+            &backup,
+            &mut files.factoids,
+            &mut files.factoid_store,
+            &mut files.quotes,
+            &mut files.tells,
+            &mut files.events,
+            &mut files.counts,
+            &mut files.seen,
+            &mut files.urls,
+        )
+        .or_exit(EXIT_FAILURE)?;
+        files.end_batch().or_exit(EXIT_CANTCREAT)?;
+
+        info!("Imported {}", path.display());
+        return Ok(());
     }
 
+    let mut dbs = Databases::open(&config, data_dir).or_exit(EXIT_UNAVAILABLE)?;
+    if !dbs.persistent {
+        return Err(format_err!(
+            "No storage is configured, set database_url, sqlite_path, data_dir or --data-dir"
+        ))
+        .or_exit(EXIT_CONFIG);
+    }
+
+    backup::import(
+        &backup,
+        &mut *dbs.factoids,
+        &mut *dbs.factoid_store,
+        &mut *dbs.quotes,
+        &mut *dbs.tells,
+        &mut *dbs.events,
+        &mut *dbs.counts,
+        &mut *dbs.seen,
+        &mut *dbs.urls,
+    )
+    .or_exit(EXIT_FAILURE)?;
+
+    info!("Imported {}", path.display());
+
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), Exit> {
+    let configs = load_configs(&args.config, args.strict)?;
+
     // Create an event loop to run the connections on.
-    let mut reactor = IrcReactor::new()?;
+    let mut reactor = IrcReactor::new().or_exit(EXIT_FAILURE)?;
 
     // Open a connection and add work for each config
-    for config in configs {
+    for bot_config in configs {
+        let config = &bot_config.irc;
         let mut prefix = None;
         let mut disabled_plugins = None;
-        let mut mysql_url = None;
         if let Some(ref options) = config.options {
             if let Some(disabled) = options.get("disabled_plugins") {
                 disabled_plugins = Some(disabled.split(',').map(|p| p.trim()).collect::<Vec<_>>());
             }
             prefix = options.get("prefix");
-
-            mysql_url = options.get("mysql_url");
         }
         let prefix = prefix.cloned().unwrap_or_else(|| String::from("."));
 
-        let mut bot = frippy::Bot::new(&prefix);
-        bot.add_plugin(Help::new());
-        bot.add_plugin(UrlTitles::new(1024));
-        bot.add_plugin(Sed::new(60));
-        bot.add_plugin(Unicode::new());
-        bot.add_plugin(KeepNick::new());
-
-        #[cfg(feature = "mysql")]
-        {
-            if let Some(url) = mysql_url {
-                use diesel::MysqlConnection;
-                use r2d2_diesel::ConnectionManager;
-
-                let manager = ConnectionManager::<MysqlConnection>::new(url.clone());
-                match r2d2::Pool::builder().build(manager) {
-                    Ok(pool) => match embedded_migrations::run(&*pool.get()?) {
-                        Ok(_) => {
-                            let pool = Arc::new(pool);
-                            bot.add_plugin(Factoid::new(pool.clone()));
-                            bot.add_plugin(Quote::new(pool.clone()));
-                            bot.add_plugin(Tell::new(pool.clone()));
-                            bot.add_plugin(Remind::new(pool.clone()));
-                            bot.add_plugin(Counter::new(pool.clone()));
-                            info!("Connected to MySQL server")
-                        }
-                        Err(e) => {
-                            bot.add_plugin(Factoid::new(HashMap::new()));
-                            bot.add_plugin(Quote::new(HashMap::new()));
-                            bot.add_plugin(Tell::new(HashMap::new()));
-                            bot.add_plugin(Remind::new(HashMap::new()));
-                            bot.add_plugin(Counter::new(HashMap::new()));
-                            error!("Failed to run migrations: {}", e);
-                        }
-                    },
-                    Err(e) => error!("Failed to connect to database: {}", e),
-                }
-            } else {
-                bot.add_plugin(Factoid::new(HashMap::new()));
-                bot.add_plugin(Quote::new(HashMap::new()));
-                bot.add_plugin(Tell::new(HashMap::new()));
-                bot.add_plugin(Remind::new(HashMap::new()));
-                bot.add_plugin(Counter::new(HashMap::new()));
-            }
-        }
-        #[cfg(not(feature = "mysql"))]
-        {
-            if mysql_url.is_some() {
-                error!("frippy was not built with the mysql feature")
-            }
-            bot.add_plugin(Factoid::new(HashMap::new()));
-            bot.add_plugin(Quote::new(HashMap::new()));
-            bot.add_plugin(Tell::new(HashMap::new()));
-            bot.add_plugin(Remind::new(HashMap::new()));
-            bot.add_plugin(Counter::new(HashMap::new()));
-        }
+        // Running without the data would lose it on the next restart
+        let dbs = Databases::open(&bot_config, args.data_dir.as_ref().map(PathBuf::as_path))
+            .with_context(|_| format!("Failed to open the storage of {}", bot_config.name))
+            .or_exit(EXIT_UNAVAILABLE)?;
+        let network = config.server.as_ref().map_or("", String::as_str);
+        let log = open_log(
+            config,
+            network,
+            args.data_dir.as_ref().map(PathBuf::as_path),
+        );
+        let http = open_http(config, &bot_config.http);
+        let mut bot = create_bot(&prefix, network, dbs, log, &http);
 
         if let Some(disabled_plugins) = disabled_plugins {
             for name in disabled_plugins {
@@ -150,11 +491,338 @@ fn run() -> Result<(), Error> {
             }
         }
 
-        bot.connect(&mut reactor, &config)?;
+        bot.connect(&mut reactor, config).or_exit(EXIT_FAILURE)?;
     }
 
     // Run the bots until they throw an error - an error could be loss of connection
-    reactor.run()?;
+    reactor.run().or_exit(EXIT_FAILURE)?;
 
     Ok(())
 }
+
+/// Creates the channel log configured by the log_dir and log_retention options.
+///
+/// The channels of each network are kept in a subdirectory named after it,
+/// so configs may share the log_dir.
+fn open_log(config: &Config, network: &str, data_dir: Option<&Path>) -> Log<IrcClient> {
+    let options = config.options.as_ref();
+    let dir = options
+        .and_then(|o| o.get("log_dir"))
+        .map(|dir| match data_dir {
+            Some(data_dir) => data_dir.join(dir),
+            None => PathBuf::from(dir),
+        })
+        .map(|dir| dir.join(network.to_lowercase().replace('/', "_")));
+    let retention = options
+        .and_then(|o| o.get("log_retention"))
+        .and_then(|r| humantime::parse_duration(r).ok())
+        .and_then(|r| chrono::Duration::from_std(r).ok());
+
+    if let Some(ref dir) = dir {
+        info!("Logging channels to {}", dir.display());
+    }
+
+    Log::new(dir, retention)
+}
+
+/// Creates the HTTP client configured by the [http] section,
+/// which may only access the private hosts in the fetch_allowlist option.
+fn open_http(config: &Config, http_config: &HttpConfig) -> Http {
+    let allowlist = config
+        .options
+        .as_ref()
+        .and_then(|o| o.get("fetch_allowlist"))
+        .map_or("", String::as_str);
+
+    let policy = Policy::with_allowlist(allowlist).unwrap_or_else(|e| {
+        error!("Ignoring fetch_allowlist: {}", e);
+        Policy::default()
+    });
+
+    Http::new(http_config, policy.clone()).unwrap_or_else(|e| {
+        error!("Ignoring the [http] section: {}", e);
+        Http::new(&HttpConfig::default(), policy).expect("the default HTTP config is valid")
+    })
+}
+
+/// Creates a bot with all plugins, they download everything through `http`.
+fn create_bot(
+    prefix: &str,
+    network: &str,
+    dbs: Databases,
+    log: Log<IrcClient>,
+    http: &Http,
+) -> Bot {
+    let mut bot = Bot::new(prefix);
+    bot.add_plugin(Help::new());
+    bot.add_plugin(log);
+    bot.add_plugin(Sed::new(60));
+    bot.add_plugin(Unicode::new());
+    bot.add_plugin(KeepNick::new());
+    dbs.add_plugins(&mut bot, network, http);
+
+    bot
+}
+
+/// The data files of a config with their concrete types.
+struct Files {
+    factoids: FileDatabase<(String, i32), FactoidEntry>,
+    factoid_store: FileDatabase<(String, String), StoredValue>,
+    quotes: FileDatabase<(String, String, i32), QuoteEntry>,
+    tells: FileDatabase<String, Vec<TellMessage>>,
+    events: FileDatabase<i64, Event>,
+    counts: FileDatabase<String, i64>,
+    seen: FileDatabase<(String, String), SeenEntry>,
+    urls: PostFile,
+}
+
+impl Files {
+    fn open(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Files {
+            factoids: FileDatabase::open(dir.join("factoids.json"))?,
+            factoid_store: FileDatabase::open(dir.join("factoid_store.json"))?,
+            quotes: FileDatabase::open(dir.join("quotes.json"))?,
+            tells: FileDatabase::open(dir.join("tells.json"))?,
+            events: FileDatabase::open(dir.join("events.json"))?,
+            counts: FileDatabase::open(dir.join("counts.json"))?,
+            seen: FileDatabase::open(dir.join("seen.json"))?,
+            urls: PostFile::open(dir.join("urls.json"))?,
+        })
+    }
+
+    /// Delays writing the files until `end_batch` is called.
+    fn begin_batch(&mut self) {
+        self.factoids.begin_batch();
+        self.factoid_store.begin_batch();
+        self.quotes.begin_batch();
+        self.tells.begin_batch();
+        self.events.begin_batch();
+        self.counts.begin_batch();
+        self.seen.begin_batch();
+        self.urls.begin_batch();
+    }
+
+    fn end_batch(&mut self) -> Result<(), Error> {
+        self.factoids.end_batch()?;
+        self.factoid_store.end_batch()?;
+        self.quotes.end_batch()?;
+        self.tells.end_batch()?;
+        self.events.end_batch()?;
+        self.counts.end_batch()?;
+        self.seen.end_batch()?;
+        self.urls.end_batch()?;
+
+        Ok(())
+    }
+}
+
+/// The storage of all plugins which save data.
+struct Databases {
+    factoids: Box<dyn FactoidDatabase>,
+    factoid_store: Box<dyn FactoidStoreDatabase>,
+    quotes: Box<dyn QuoteDatabase>,
+    tells: Box<dyn TellDatabase>,
+    events: Box<dyn RemindDatabase>,
+    counts: Box<dyn CounterDatabase>,
+    seen: Box<dyn SeenDatabase>,
+    urls: Box<dyn UrlDatabase>,
+    /// False if the data is only kept in memory.
+    persistent: bool,
+}
+
+impl Databases {
+    /// Opens the storage selected by the options of `config`.
+    ///
+    /// Relative paths are resolved against `data_dir`. If no other storage
+    /// is configured the data files are kept in a subdirectory of it
+    /// named after the config file, so every config has its own files.
+    fn open(config: &BotConfig, data_dir: Option<&Path>) -> Result<Self, Error> {
+        let resolve = |path: &str| match data_dir {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+
+        let options = config.irc.options.as_ref();
+        // mysql_url is still accepted for older configs
+        let database_url =
+            options.and_then(|o| o.get("database_url").or_else(|| o.get("mysql_url")));
+
+        if let Some(url) = database_url {
+            Self::from_url(url, &resolve)
+        } else if let Some(path) = options.and_then(|o| o.get("sqlite_path")) {
+            Self::sqlite(&resolve(path))
+        } else if let Some(dir) = Self::files_dir(config, data_dir) {
+            Self::files(&dir)
+        } else {
+            Ok(Self::hashmap())
+        }
+    }
+
+    /// Returns the directory of the data files if `config` does not use a database.
+    fn files_dir(config: &BotConfig, data_dir: Option<&Path>) -> Option<PathBuf> {
+        let options = config.irc.options.as_ref();
+        let uses_database = options.map_or(false, |o| {
+            ["database_url", "mysql_url", "sqlite_path"]
+                .iter()
+                .any(|&key| o.contains_key(key))
+        });
+
+        if uses_database {
+            None
+        } else if let Some(dir) = options.and_then(|o| o.get("data_dir")) {
+            Some(match data_dir {
+                Some(data_dir) => data_dir.join(dir),
+                None => PathBuf::from(dir),
+            })
+        } else {
+            data_dir.map(|dir| dir.join(&config.name))
+        }
+    }
+
+    fn hashmap() -> Self {
+        Databases {
+            factoids: Box::new(HashMap::new()),
+            factoid_store: Box::new(HashMap::new()),
+            quotes: Box::new(HashMap::new()),
+            tells: Box::new(HashMap::new()),
+            events: Box::new(HashMap::new()),
+            counts: Box::new(HashMap::new()),
+            seen: Box::new(HashMap::new()),
+            urls: Box::new(PostMap::new()),
+            persistent: false,
+        }
+    }
+
+    fn files(dir: &Path) -> Result<Self, Error> {
+        let files = Files::open(dir)?;
+        info!("Storing plugin data in {}", dir.display());
+
+        Ok(Databases {
+            factoids: Box::new(files.factoids),
+            factoid_store: Box::new(files.factoid_store),
+            quotes: Box::new(files.quotes),
+            tells: Box::new(files.tells),
+            events: Box::new(files.events),
+            counts: Box::new(files.counts),
+            seen: Box::new(files.seen),
+            urls: Box::new(files.urls),
+            persistent: true,
+        })
+    }
+
+    /// Picks the database backend based on the scheme of `url`.
+    fn from_url(url: &str, resolve: &dyn Fn(&str) -> PathBuf) -> Result<Self, Error> {
+        let (scheme, rest) = match url.find("://") {
+            Some(idx) => (&url[..idx], &url[idx + 3..]),
+            None => ("", url),
+        };
+
+        match scheme {
+            "mysql" => Self::mysql(url),
+            "postgres" | "postgresql" => Self::postgres(url),
+            "sqlite" => Self::sqlite(&resolve(rest)),
+            _ => bail!("Unsupported database url scheme \"{}\"", scheme),
+        }
+    }
+
+    #[cfg(feature = "mysql")]
+    fn mysql(url: &str) -> Result<Self, Error> {
+        use diesel::MysqlConnection;
+
+        let manager = ConnectionManager::<MysqlConnection>::new(url);
+        let pool = Pool::builder().build(manager)?;
+        mysql::embedded_migrations::run(&*pool.get()?)?;
+        info!("Connected to MySQL server");
+
+        Ok(Self::from_pool(pool))
+    }
+
+    #[cfg(not(feature = "mysql"))]
+    fn mysql(_: &str) -> Result<Self, Error> {
+        bail!("frippy was not built with the mysql feature")
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn sqlite(path: &Path) -> Result<Self, Error> {
+        use diesel::SqliteConnection;
+
+        let manager = ConnectionManager::<SqliteConnection>::new(path.to_string_lossy());
+        // SQLite only allows a single writer at a time
+        let pool = Pool::builder().max_size(1).build(manager)?;
+        sqlite::embedded_migrations::run(&*pool.get()?)?;
+        info!("Opened SQLite database {}", path.display());
+
+        Ok(Self::from_pool(pool))
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn sqlite(_: &Path) -> Result<Self, Error> {
+        bail!("frippy was not built with the sqlite feature")
+    }
+
+    #[cfg(feature = "postgres")]
+    fn postgres(url: &str) -> Result<Self, Error> {
+        use diesel::PgConnection;
+
+        let manager = ConnectionManager::<PgConnection>::new(url);
+        let pool = Pool::builder().build(manager)?;
+        postgres::embedded_migrations::run(&*pool.get()?)?;
+        info!("Connected to PostgreSQL server");
+
+        Ok(Self::from_pool(pool))
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    fn postgres(_: &str) -> Result<Self, Error> {
+        bail!("frippy was not built with the postgres feature")
+    }
+
+    #[cfg(feature = "sql")]
+    fn from_pool<Conn>(pool: Pool<ConnectionManager<Conn>>) -> Self
+    where
+        Conn: diesel::Connection + Send + 'static,
+        Arc<Pool<ConnectionManager<Conn>>>: FactoidDatabase
+            + FactoidStoreDatabase
+            + QuoteDatabase
+            + TellDatabase
+            + RemindDatabase
+            + CounterDatabase
+            + SeenDatabase
+            + UrlDatabase,
+    {
+        let pool = Arc::new(pool);
+
+        Databases {
+            factoids: Box::new(pool.clone()),
+            factoid_store: Box::new(pool.clone()),
+            quotes: Box::new(pool.clone()),
+            tells: Box::new(pool.clone()),
+            events: Box::new(pool.clone()),
+            counts: Box::new(pool.clone()),
+            seen: Box::new(pool.clone()),
+            urls: Box::new(pool),
+            persistent: true,
+        }
+    }
+
+    /// Adds the plugins which use the databases, `network` separates
+    /// the data of plugins which store it per network.
+    fn add_plugins(self, bot: &mut Bot, network: &str, http: &Http) {
+        bot.add_plugin(Factoid::new(
+            self.factoids,
+            self.factoid_store,
+            http.clone(),
+        ));
+        bot.add_plugin(Quote::new(self.quotes));
+        bot.add_plugin(Tell::new(self.tells));
+        bot.add_plugin(Remind::new(self.events));
+        bot.add_plugin(Counter::new(self.counts));
+        bot.add_plugin(Seen::new(self.seen, network));
+
+        let titles = UrlTitles::new(self.urls, network, 1024, http.clone());
+        bot.add_plugin(titles.history());
+        bot.add_plugin(titles);
+    }
+}
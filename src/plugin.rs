@@ -57,6 +57,8 @@ pub trait PluginName {
 pub struct PluginCommand {
     /// The sender of the command.
     pub source: String,
+    /// The `nick!user@host` of the sender, empty if the server did not send it.
+    pub prefix: String,
     /// If the command was sent to a channel, this will be that channel
     /// otherwise it is the same as `source`.
     pub target: String,
@@ -83,6 +85,7 @@ impl PluginCommand {
 
             Some(PluginCommand {
                 source: message.source_nickname().unwrap().to_string(),
+                prefix: message.prefix.clone().unwrap_or_default(),
                 target: message.response_target().unwrap().to_string(),
                 tokens,
             })
@@ -1,26 +1,34 @@
 use std::collections::HashMap;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use std::sync::Arc;
 
 #[cfg(feature = "mysql")]
 use diesel::mysql::MysqlConnection;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+#[cfg(feature = "sql")]
 use diesel::prelude::*;
-#[cfg(feature = "mysql")]
-use failure::ResultExt;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+#[cfg(feature = "sql")]
 use r2d2::Pool;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use r2d2_diesel::ConnectionManager;
 
+use failure::ResultExt;
+
 use super::error::*;
+use crate::storage::FileDatabase;
 
 pub trait Database: Send + Sync {
     fn add(&mut self, name: &str) -> Result<i64, CounterError>;
     fn subtract(&mut self, name: &str) -> Result<i64, CounterError>;
     fn get_count(&self, name: &str) -> Result<i64, CounterError>;
+    fn list_counts(&self) -> Result<Vec<(String, i64)>, CounterError>;
+    fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError>;
 }
 
+// HashMap
 impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, i64, S> {
     fn add(&mut self, name: &str) -> Result<i64, CounterError> {
         Ok(*self
@@ -37,11 +45,76 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, i64
     fn get_count(&self, name: &str) -> Result<i64, CounterError> {
         Ok(self.get(name).copied().unwrap_or(0))
     }
+
+    fn list_counts(&self) -> Result<Vec<(String, i64)>, CounterError> {
+        Ok(self
+            .iter()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect())
+    }
+
+    fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError> {
+        self.insert(name.to_owned(), count);
+        Ok(())
+    }
+}
+
+// File
+impl Database for FileDatabase<String, i64> {
+    fn add(&mut self, name: &str) -> Result<i64, CounterError> {
+        let count = self.map_mut().add(name)?;
+        self.flush().context(ErrorKind::FileError)?;
+
+        Ok(count)
+    }
+
+    fn subtract(&mut self, name: &str) -> Result<i64, CounterError> {
+        let count = self.map_mut().subtract(name)?;
+        self.flush().context(ErrorKind::FileError)?;
+
+        Ok(count)
+    }
+
+    fn get_count(&self, name: &str) -> Result<i64, CounterError> {
+        self.map().get_count(name)
+    }
+
+    fn list_counts(&self) -> Result<Vec<(String, i64)>, CounterError> {
+        self.map().list_counts()
+    }
+
+    fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError> {
+        self.map_mut().set_count(name, count)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+}
+
+// Box
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn add(&mut self, name: &str) -> Result<i64, CounterError> {
+        (**self).add(name)
+    }
+
+    fn subtract(&mut self, name: &str) -> Result<i64, CounterError> {
+        (**self).subtract(name)
+    }
+
+    fn get_count(&self, name: &str) -> Result<i64, CounterError> {
+        (**self).get_count(name)
+    }
+
+    fn list_counts(&self) -> Result<Vec<(String, i64)>, CounterError> {
+        (**self).list_counts()
+    }
+
+    fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError> {
+        (**self).set_count(name, count)
+    }
 }
 
 // Diesel automatically defines the counts module as public.
 // We create a schema module to keep it private.
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 mod schema {
     diesel::table! {
         counts (name) {
@@ -51,86 +124,138 @@ mod schema {
     }
 }
 
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use self::schema::counts;
 
-#[cfg(feature = "mysql")]
-impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
-    fn add(&mut self, name: &str) -> Result<i64, CounterError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        match counts::table
-            .find(name)
-            .select(counts::columns::count)
-            .first(conn)
-        {
-            Ok(mut count) => {
-                count += 1;
-                diesel::update(counts::table.filter(counts::columns::name.eq(name)))
-                    .set(counts::columns::count.eq(count))
-                    .execute(conn)
-                    .context(ErrorKind::MysqlError)?;
-
-                Ok(count)
+// The queries are the same for every backend
+#[cfg(feature = "sql")]
+macro_rules! impl_database {
+    ($connection:ty, $error:ident) => {
+        impl Database for Arc<Pool<ConnectionManager<$connection>>> {
+            fn add(&mut self, name: &str) -> Result<i64, CounterError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                match counts::table
+                    .find(name)
+                    .select(counts::columns::count)
+                    .first(conn)
+                {
+                    Ok(mut count) => {
+                        count += 1;
+                        diesel::update(counts::table.filter(counts::columns::name.eq(name)))
+                            .set(counts::columns::count.eq(count))
+                            .execute(conn)
+                            .context(ErrorKind::$error)?;
+
+                        Ok(count)
+                    }
+                    Err(e) => match e {
+                        diesel::result::Error::NotFound => {
+                            diesel::insert_into(counts::table)
+                                .values((
+                                    counts::columns::name.eq(name),
+                                    counts::columns::count.eq(1),
+                                ))
+                                .execute(conn)
+                                .context(ErrorKind::$error)?;
+
+                            Ok(1)
+                        }
+                        _ => Err(e).context(ErrorKind::$error)?,
+                    },
+                }
             }
-            Err(e) => match e {
-                diesel::result::Error::NotFound => {
-                    diesel::insert_into(counts::table)
-                        .values((counts::columns::name.eq(name), counts::columns::count.eq(1)))
-                        .execute(conn)
-                        .context(ErrorKind::MysqlError)?;
+            fn subtract(&mut self, name: &str) -> Result<i64, CounterError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                match counts::table
+                    .find(name)
+                    .select(counts::columns::count)
+                    .first(conn)
+                {
+                    Ok(mut count) => {
+                        count -= 1;
+                        diesel::update(counts::table.filter(counts::columns::name.eq(name)))
+                            .set(counts::columns::count.eq(count))
+                            .execute(conn)
+                            .context(ErrorKind::$error)?;
 
-                    Ok(1)
+                        Ok(count)
+                    }
+                    Err(e) => match e {
+                        diesel::result::Error::NotFound => {
+                            diesel::insert_into(counts::table)
+                                .values((
+                                    counts::columns::name.eq(name),
+                                    counts::columns::count.eq(-1),
+                                ))
+                                .execute(conn)
+                                .context(ErrorKind::$error)?;
+
+                            Ok(-1)
+                        }
+                        _ => Err(e).context(ErrorKind::$error)?,
+                    },
                 }
-                _ => Err(e).context(ErrorKind::MysqlError)?,
-            },
-        }
-    }
-    fn subtract(&mut self, name: &str) -> Result<i64, CounterError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        match counts::table
-            .find(name)
-            .select(counts::columns::count)
-            .first(conn)
-        {
-            Ok(mut count) => {
-                count -= 1;
-                diesel::update(counts::table.filter(counts::columns::name.eq(name)))
-                    .set(counts::columns::count.eq(count))
-                    .execute(conn)
-                    .context(ErrorKind::MysqlError)?;
-
-                Ok(count)
             }
-            Err(e) => match e {
-                diesel::result::Error::NotFound => {
+
+            fn get_count(&self, name: &str) -> Result<i64, CounterError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                match counts::table
+                    .find(name)
+                    .select(counts::columns::count)
+                    .first(conn)
+                {
+                    Ok(count) => Ok(count),
+                    Err(e) => match e {
+                        diesel::result::Error::NotFound => Ok(0),
+                        _ => Err(e).context(ErrorKind::$error)?,
+                    },
+                }
+            }
+
+            fn list_counts(&self) -> Result<Vec<(String, i64)>, CounterError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(counts::table
+                    .load::<(String, i64)>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                // MySQL reports 0 affected rows if nothing changed so an update
+                // can not be used to detect whether the row exists
+                let exists = diesel::select(diesel::dsl::exists(counts::table.find(name)))
+                    .get_result::<bool>(conn)
+                    .context(ErrorKind::$error)?;
+
+                if exists {
+                    diesel::update(counts::table.find(name))
+                        .set(counts::columns::count.eq(count))
+                        .execute(conn)
+                        .context(ErrorKind::$error)?;
+                } else {
                     diesel::insert_into(counts::table)
                         .values((
                             counts::columns::name.eq(name),
-                            counts::columns::count.eq(-1),
+                            counts::columns::count.eq(count),
                         ))
                         .execute(conn)
-                        .context(ErrorKind::MysqlError)?;
-
-                    Ok(-1)
+                        .context(ErrorKind::$error)?;
                 }
-                _ => Err(e).context(ErrorKind::MysqlError)?,
-            },
-        }
-    }
 
-    fn get_count(&self, name: &str) -> Result<i64, CounterError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-
-        match counts::table
-            .find(name)
-            .select(counts::columns::count)
-            .first(conn)
-        {
-            Ok(count) => Ok(count),
-            Err(e) => match e {
-                diesel::result::Error::NotFound => Ok(0),
-                _ => Err(e).context(ErrorKind::MysqlError)?,
-            },
+                Ok(())
+            }
         }
-    }
+    };
 }
+
+#[cfg(feature = "mysql")]
+impl_database!(MysqlConnection, MysqlError);
+
+#[cfg(feature = "sqlite")]
+impl_database!(SqliteConnection, SqliteError);
+
+#[cfg(feature = "postgres")]
+impl_database!(PgConnection, PostgresError);
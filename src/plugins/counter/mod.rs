@@ -112,10 +112,24 @@ pub mod error {
     #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
     #[error = "CounterError"]
     pub enum ErrorKind {
+        /// File error
+        #[fail(display = "Failed to save to the data file")]
+        FileError,
+
         /// MySQL error
         #[fail(display = "Failed to execute MySQL Query")]
         MysqlError,
 
+        /// SQLite error
+        #[cfg(feature = "sqlite")]
+        #[fail(display = "Failed to execute SQLite Query")]
+        SqliteError,
+
+        /// PostgreSQL error
+        #[cfg(feature = "postgres")]
+        #[fail(display = "Failed to execute PostgreSQL Query")]
+        PostgresError,
+
         /// No connection error
         #[fail(display = "No connection to the database")]
         NoConnection,
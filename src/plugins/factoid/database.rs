@@ -1,47 +1,86 @@
-use std::collections::HashMap;
-#[cfg(feature = "mysql")]
+use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "sql")]
 use std::sync::Arc;
 
 #[cfg(feature = "mysql")]
 use diesel::mysql::MysqlConnection;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+#[cfg(feature = "sql")]
 use diesel::prelude::*;
-#[cfg(feature = "mysql")]
-use failure::ResultExt;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+#[cfg(feature = "sql")]
 use r2d2::Pool;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use r2d2_diesel::ConnectionManager;
 
 use chrono::NaiveDateTime;
 
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+
 use super::error::*;
+use crate::storage::FileDatabase;
 
-#[cfg_attr(feature = "mysql", derive(Queryable))]
-#[derive(Clone, Debug)]
+#[cfg_attr(feature = "sql", derive(Queryable))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Factoid {
     pub name: String,
     pub idx: i32,
     pub content: String,
     pub author: String,
     pub created: NaiveDateTime,
+    /// Set on every version, missing in data saved before factoids could be locked.
+    #[serde(default)]
+    pub locked: bool,
 }
 
-#[cfg_attr(feature = "mysql", derive(Insertable))]
-#[cfg_attr(feature = "mysql", table_name = "factoids")]
+#[cfg_attr(feature = "sql", derive(Insertable))]
+#[cfg_attr(feature = "sql", table_name = "factoids")]
 pub struct NewFactoid<'a> {
     pub name: &'a str,
     pub idx: i32,
     pub content: &'a str,
     pub author: &'a str,
     pub created: NaiveDateTime,
+    pub locked: bool,
+}
+
+/// A value which a Lua factoid saved in its store, encoded as JSON.
+#[cfg_attr(feature = "sql", derive(Queryable))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredValue {
+    pub factoid: String,
+    pub store_key: String,
+    pub value: String,
+}
+
+#[cfg_attr(feature = "sql", derive(Insertable))]
+#[cfg_attr(feature = "sql", table_name = "factoid_store")]
+pub struct NewStoredValue<'a> {
+    pub factoid: &'a str,
+    pub store_key: &'a str,
+    pub value: &'a str,
 }
 
 pub trait Database: Send + Sync {
     fn insert_factoid(&mut self, factoid: &NewFactoid) -> Result<(), FactoidError>;
     fn get_factoid(&self, name: &str, idx: i32) -> Result<Factoid, FactoidError>;
+    /// Returns the versions of `name` from `from` up to but excluding `to`, oldest first.
+    fn get_factoids(&self, name: &str, from: i32, to: i32) -> Result<Vec<Factoid>, FactoidError>;
     fn delete_factoid(&mut self, name: &str, idx: i32) -> Result<(), FactoidError>;
+    /// Locks or unlocks every version of `name`.
+    fn set_locked(&mut self, name: &str, locked: bool) -> Result<(), FactoidError>;
     fn count_factoids(&self, name: &str) -> Result<i32, FactoidError>;
+    /// Returns up to `limit` names which start with `prefix` in alphabetical order.
+    fn list_names(&self, prefix: &str, limit: i64) -> Result<Vec<String>, FactoidError>;
+    /// Returns up to `limit` versions whose name or content contains `query`,
+    /// ordered by name and newest first.
+    fn search_factoids(&self, query: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError>;
+    /// Returns up to `limit` versions added by `author`, newest first.
+    fn get_factoids_by(&self, author: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError>;
+    fn list_factoids(&self) -> Result<Vec<Factoid>, FactoidError>;
 }
 
 // HashMap
@@ -53,6 +92,7 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<(String, i3
             content: factoid.content.to_owned(),
             author: factoid.author.to_owned(),
             created: factoid.created,
+            locked: factoid.locked,
         };
 
         let name = factoid.name.clone();
@@ -69,6 +109,17 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<(String, i3
             .ok_or(ErrorKind::NotFound)?)
     }
 
+    fn get_factoids(&self, name: &str, from: i32, to: i32) -> Result<Vec<Factoid>, FactoidError> {
+        let mut factoids = self
+            .values()
+            .filter(|f| f.name == name && f.idx >= from && f.idx < to)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        factoids.sort_by_key(|f| f.idx);
+        Ok(factoids)
+    }
+
     fn delete_factoid(&mut self, name: &str, idx: i32) -> Result<(), FactoidError> {
         match self.remove(&(name.to_owned(), idx)) {
             Some(_) => Ok(()),
@@ -76,14 +127,279 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<(String, i3
         }
     }
 
+    fn set_locked(&mut self, name: &str, locked: bool) -> Result<(), FactoidError> {
+        let mut found = false;
+        for factoid in self.values_mut().filter(|f| f.name == name) {
+            factoid.locked = locked;
+            found = true;
+        }
+
+        if found {
+            Ok(())
+        } else {
+            Err(ErrorKind::NotFound)?
+        }
+    }
+
     fn count_factoids(&self, name: &str) -> Result<i32, FactoidError> {
         Ok(self.iter().filter(|&(&(ref n, _), _)| n == name).count() as i32)
     }
+
+    fn list_names(&self, prefix: &str, limit: i64) -> Result<Vec<String>, FactoidError> {
+        Ok(self
+            .keys()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, _)| name)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn search_factoids(&self, query: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError> {
+        let query = query.to_lowercase();
+        let mut factoids = self
+            .values()
+            .filter(|f| {
+                f.name.to_lowercase().contains(&query) || f.content.to_lowercase().contains(&query)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        factoids.sort_by(|a, b| (&a.name, b.idx).cmp(&(&b.name, a.idx)));
+        factoids.truncate(limit as usize);
+
+        Ok(factoids)
+    }
+
+    fn get_factoids_by(&self, author: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError> {
+        let mut factoids = self
+            .values()
+            .filter(|f| f.author == author)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        factoids.sort_by(|a, b| (b.created, &b.name).cmp(&(a.created, &a.name)));
+        factoids.truncate(limit as usize);
+
+        Ok(factoids)
+    }
+
+    fn list_factoids(&self) -> Result<Vec<Factoid>, FactoidError> {
+        Ok(self.values().cloned().collect())
+    }
+}
+
+// File
+impl Database for FileDatabase<(String, i32), Factoid> {
+    fn insert_factoid(&mut self, factoid: &NewFactoid) -> Result<(), FactoidError> {
+        self.map_mut().insert_factoid(factoid)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn get_factoid(&self, name: &str, idx: i32) -> Result<Factoid, FactoidError> {
+        self.map().get_factoid(name, idx)
+    }
+
+    fn get_factoids(&self, name: &str, from: i32, to: i32) -> Result<Vec<Factoid>, FactoidError> {
+        self.map().get_factoids(name, from, to)
+    }
+
+    fn delete_factoid(&mut self, name: &str, idx: i32) -> Result<(), FactoidError> {
+        self.map_mut().delete_factoid(name, idx)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn set_locked(&mut self, name: &str, locked: bool) -> Result<(), FactoidError> {
+        self.map_mut().set_locked(name, locked)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn count_factoids(&self, name: &str) -> Result<i32, FactoidError> {
+        self.map().count_factoids(name)
+    }
+
+    fn list_names(&self, prefix: &str, limit: i64) -> Result<Vec<String>, FactoidError> {
+        self.map().list_names(prefix, limit)
+    }
+
+    fn search_factoids(&self, query: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError> {
+        self.map().search_factoids(query, limit)
+    }
+
+    fn get_factoids_by(&self, author: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError> {
+        self.map().get_factoids_by(author, limit)
+    }
+
+    fn list_factoids(&self) -> Result<Vec<Factoid>, FactoidError> {
+        self.map().list_factoids()
+    }
+}
+
+// Box
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn insert_factoid(&mut self, factoid: &NewFactoid) -> Result<(), FactoidError> {
+        (**self).insert_factoid(factoid)
+    }
+
+    fn get_factoid(&self, name: &str, idx: i32) -> Result<Factoid, FactoidError> {
+        (**self).get_factoid(name, idx)
+    }
+
+    fn get_factoids(&self, name: &str, from: i32, to: i32) -> Result<Vec<Factoid>, FactoidError> {
+        (**self).get_factoids(name, from, to)
+    }
+
+    fn delete_factoid(&mut self, name: &str, idx: i32) -> Result<(), FactoidError> {
+        (**self).delete_factoid(name, idx)
+    }
+
+    fn set_locked(&mut self, name: &str, locked: bool) -> Result<(), FactoidError> {
+        (**self).set_locked(name, locked)
+    }
+
+    fn count_factoids(&self, name: &str) -> Result<i32, FactoidError> {
+        (**self).count_factoids(name)
+    }
+
+    fn list_names(&self, prefix: &str, limit: i64) -> Result<Vec<String>, FactoidError> {
+        (**self).list_names(prefix, limit)
+    }
+
+    fn search_factoids(&self, query: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError> {
+        (**self).search_factoids(query, limit)
+    }
+
+    fn get_factoids_by(&self, author: &str, limit: i64) -> Result<Vec<Factoid>, FactoidError> {
+        (**self).get_factoids_by(author, limit)
+    }
+
+    fn list_factoids(&self) -> Result<Vec<Factoid>, FactoidError> {
+        (**self).list_factoids()
+    }
+}
+
+/// The stores of Lua factoids, every factoid has its own keys.
+pub trait StoreDatabase: Send + Sync {
+    /// Replaces the value if the key exists already.
+    fn set_value(&mut self, value: &NewStoredValue) -> Result<(), FactoidError>;
+    fn get_value(&self, factoid: &str, key: &str) -> Result<StoredValue, FactoidError>;
+    fn delete_value(&mut self, factoid: &str, key: &str) -> Result<(), FactoidError>;
+    /// Deletes every value saved by `factoid`, it is fine if there are none.
+    fn delete_values(&mut self, factoid: &str) -> Result<(), FactoidError>;
+    /// Returns every value saved by `factoid`.
+    fn get_values(&self, factoid: &str) -> Result<Vec<StoredValue>, FactoidError>;
+    fn list_values(&self) -> Result<Vec<StoredValue>, FactoidError>;
+}
+
+// HashMap
+impl<S: ::std::hash::BuildHasher + Send + Sync> StoreDatabase
+    for HashMap<(String, String), StoredValue, S>
+{
+    fn set_value(&mut self, value: &NewStoredValue) -> Result<(), FactoidError> {
+        let value = StoredValue {
+            factoid: value.factoid.to_owned(),
+            store_key: value.store_key.to_owned(),
+            value: value.value.to_owned(),
+        };
+
+        self.insert((value.factoid.clone(), value.store_key.clone()), value);
+        Ok(())
+    }
+
+    fn get_value(&self, factoid: &str, key: &str) -> Result<StoredValue, FactoidError> {
+        Ok(self
+            .get(&(factoid.to_owned(), key.to_owned()))
+            .cloned()
+            .ok_or(ErrorKind::NotFound)?)
+    }
+
+    fn delete_value(&mut self, factoid: &str, key: &str) -> Result<(), FactoidError> {
+        match self.remove(&(factoid.to_owned(), key.to_owned())) {
+            Some(_) => Ok(()),
+            None => Err(ErrorKind::NotFound)?,
+        }
+    }
+
+    fn delete_values(&mut self, factoid: &str) -> Result<(), FactoidError> {
+        self.retain(|_, v| v.factoid != factoid);
+        Ok(())
+    }
+
+    fn get_values(&self, factoid: &str) -> Result<Vec<StoredValue>, FactoidError> {
+        Ok(self
+            .values()
+            .filter(|v| v.factoid == factoid)
+            .cloned()
+            .collect())
+    }
+
+    fn list_values(&self) -> Result<Vec<StoredValue>, FactoidError> {
+        Ok(self.values().cloned().collect())
+    }
+}
+
+// File
+impl StoreDatabase for FileDatabase<(String, String), StoredValue> {
+    fn set_value(&mut self, value: &NewStoredValue) -> Result<(), FactoidError> {
+        self.map_mut().set_value(value)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn get_value(&self, factoid: &str, key: &str) -> Result<StoredValue, FactoidError> {
+        self.map().get_value(factoid, key)
+    }
+
+    fn delete_value(&mut self, factoid: &str, key: &str) -> Result<(), FactoidError> {
+        self.map_mut().delete_value(factoid, key)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn delete_values(&mut self, factoid: &str) -> Result<(), FactoidError> {
+        self.map_mut().delete_values(factoid)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn get_values(&self, factoid: &str) -> Result<Vec<StoredValue>, FactoidError> {
+        self.map().get_values(factoid)
+    }
+
+    fn list_values(&self) -> Result<Vec<StoredValue>, FactoidError> {
+        self.map().list_values()
+    }
+}
+
+// Box
+impl<D: StoreDatabase + ?Sized> StoreDatabase for Box<D> {
+    fn set_value(&mut self, value: &NewStoredValue) -> Result<(), FactoidError> {
+        (**self).set_value(value)
+    }
+
+    fn get_value(&self, factoid: &str, key: &str) -> Result<StoredValue, FactoidError> {
+        (**self).get_value(factoid, key)
+    }
+
+    fn delete_value(&mut self, factoid: &str, key: &str) -> Result<(), FactoidError> {
+        (**self).delete_value(factoid, key)
+    }
+
+    fn delete_values(&mut self, factoid: &str) -> Result<(), FactoidError> {
+        (**self).delete_values(factoid)
+    }
+
+    fn get_values(&self, factoid: &str) -> Result<Vec<StoredValue>, FactoidError> {
+        (**self).get_values(factoid)
+    }
+
+    fn list_values(&self) -> Result<Vec<StoredValue>, FactoidError> {
+        (**self).list_values()
+    }
 }
 
 // Diesel automatically defines the factoids module as public.
 // We create a schema module to keep it private.
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 mod schema {
     table! {
         factoids (name, idx) {
@@ -92,68 +408,271 @@ mod schema {
             content -> Text,
             author -> Varchar,
             created -> Timestamp,
+            locked -> Bool,
+        }
+    }
+
+    table! {
+        factoid_store (factoid, store_key) {
+            factoid -> Varchar,
+            store_key -> Varchar,
+            value -> Text,
         }
     }
 }
 
-#[cfg(feature = "mysql")]
-use self::schema::factoids;
+#[cfg(feature = "sql")]
+use self::schema::{factoid_store, factoids};
+#[cfg(feature = "sql")]
+use crate::utils::escape_like;
 
-#[cfg(feature = "mysql")]
-impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
-    fn insert_factoid(&mut self, factoid: &NewFactoid) -> Result<(), FactoidError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+// LIKE is case-sensitive on Postgres and SQLite
+#[cfg(feature = "sql")]
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
 
-        diesel::insert_into(factoids::table)
-            .values(factoid)
-            .execute(conn)
-            .context(ErrorKind::MysqlError)?;
+// The queries are the same for every backend
+#[cfg(feature = "sql")]
+macro_rules! impl_database {
+    ($connection:ty, $error:ident) => {
+        impl Database for Arc<Pool<ConnectionManager<$connection>>> {
+            fn insert_factoid(&mut self, factoid: &NewFactoid) -> Result<(), FactoidError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
 
-        Ok(())
-    }
+                diesel::insert_into(factoids::table)
+                    .values(factoid)
+                    .execute(conn)
+                    .context(ErrorKind::$error)?;
 
-    fn get_factoid(&self, name: &str, idx: i32) -> Result<Factoid, FactoidError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        Ok(factoids::table
-            .find((name, idx))
-            .first(conn)
-            .context(ErrorKind::MysqlError)?)
-    }
+                Ok(())
+            }
 
-    fn delete_factoid(&mut self, name: &str, idx: i32) -> Result<(), FactoidError> {
-        use self::factoids::columns;
-
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        match diesel::delete(
-            factoids::table
-                .filter(columns::name.eq(name))
-                .filter(columns::idx.eq(idx)),
-        )
-        .execute(conn)
-        {
-            Ok(v) => {
-                if v > 0 {
-                    Ok(())
-                } else {
-                    Err(ErrorKind::NotFound)?
+            fn get_factoid(&self, name: &str, idx: i32) -> Result<Factoid, FactoidError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                Ok(factoids::table
+                    .find((name, idx))
+                    .first(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn get_factoids(
+                &self,
+                name: &str,
+                from: i32,
+                to: i32,
+            ) -> Result<Vec<Factoid>, FactoidError> {
+                use self::factoids::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(factoids::table
+                    .filter(columns::name.eq(name))
+                    .filter(columns::idx.ge(from))
+                    .filter(columns::idx.lt(to))
+                    .order(columns::idx.asc())
+                    .load::<Factoid>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn delete_factoid(&mut self, name: &str, idx: i32) -> Result<(), FactoidError> {
+                use self::factoids::columns;
+
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                match diesel::delete(
+                    factoids::table
+                        .filter(columns::name.eq(name))
+                        .filter(columns::idx.eq(idx)),
+                )
+                .execute(conn)
+                {
+                    Ok(v) => {
+                        if v > 0 {
+                            Ok(())
+                        } else {
+                            Err(ErrorKind::NotFound)?
+                        }
+                    }
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
+
+            fn set_locked(&mut self, name: &str, locked: bool) -> Result<(), FactoidError> {
+                use self::factoids::columns;
+
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                match diesel::update(factoids::table.filter(columns::name.eq(name)))
+                    .set(columns::locked.eq(locked))
+                    .execute(conn)
+                {
+                    Ok(0) => Err(ErrorKind::NotFound)?,
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
+
+            fn count_factoids(&self, name: &str) -> Result<i32, FactoidError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                let count: Result<i64, _> = factoids::table
+                    .filter(factoids::columns::name.eq(name))
+                    .count()
+                    .get_result(conn);
+
+                match count {
+                    Ok(c) => Ok(c as i32),
+                    Err(diesel::NotFound) => Ok(0),
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
                 }
             }
-            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+
+            fn list_names(&self, prefix: &str, limit: i64) -> Result<Vec<String>, FactoidError> {
+                use self::factoids::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                // Postgres uses the factoids_name_pattern index for this
+                Ok(factoids::table
+                    .select(columns::name)
+                    .filter(
+                        columns::name
+                            .like(format!("{}%", escape_like(prefix)))
+                            .escape('\\'),
+                    )
+                    .distinct()
+                    .order(columns::name.asc())
+                    .limit(limit)
+                    .load::<String>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn search_factoids(
+                &self,
+                query: &str,
+                limit: i64,
+            ) -> Result<Vec<Factoid>, FactoidError> {
+                use self::factoids::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let pattern = format!("%{}%", escape_like(&query.to_lowercase()));
+                let name_matches = lower(columns::name).like(&pattern).escape('\\');
+                let content_matches = lower(columns::content).like(&pattern).escape('\\');
+
+                Ok(factoids::table
+                    .filter(name_matches.or(content_matches))
+                    .order((columns::name.asc(), columns::idx.desc()))
+                    .limit(limit)
+                    .load::<Factoid>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn get_factoids_by(
+                &self,
+                author: &str,
+                limit: i64,
+            ) -> Result<Vec<Factoid>, FactoidError> {
+                use self::factoids::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(factoids::table
+                    .filter(columns::author.eq(author))
+                    .order((columns::created.desc(), columns::name.desc()))
+                    .limit(limit)
+                    .load::<Factoid>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn list_factoids(&self) -> Result<Vec<Factoid>, FactoidError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(factoids::table
+                    .load::<Factoid>(conn)
+                    .context(ErrorKind::$error)?)
+            }
         }
-    }
 
-    fn count_factoids(&self, name: &str) -> Result<i32, FactoidError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+        impl StoreDatabase for Arc<Pool<ConnectionManager<$connection>>> {
+            fn set_value(&mut self, value: &NewStoredValue) -> Result<(), FactoidError> {
+                use self::factoid_store::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                // Upserts are written differently by every backend
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    diesel::delete(
+                        factoid_store::table
+                            .filter(columns::factoid.eq(value.factoid))
+                            .filter(columns::store_key.eq(value.store_key)),
+                    )
+                    .execute(conn)?;
+
+                    diesel::insert_into(factoid_store::table)
+                        .values(value)
+                        .execute(conn)
+                })
+                .context(ErrorKind::$error)?;
+
+                Ok(())
+            }
+
+            fn get_value(&self, factoid: &str, key: &str) -> Result<StoredValue, FactoidError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                match factoid_store::table.find((factoid, key)).first(conn) {
+                    Ok(value) => Ok(value),
+                    Err(diesel::NotFound) => Err(ErrorKind::NotFound)?,
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
+
+            fn delete_value(&mut self, factoid: &str, key: &str) -> Result<(), FactoidError> {
+                use self::factoid_store::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                match diesel::delete(
+                    factoid_store::table
+                        .filter(columns::factoid.eq(factoid))
+                        .filter(columns::store_key.eq(key)),
+                )
+                .execute(conn)
+                {
+                    Ok(0) => Err(ErrorKind::NotFound)?,
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
 
-        let count: Result<i64, _> = factoids::table
-            .filter(factoids::columns::name.eq(name))
-            .count()
-            .get_result(conn);
+            fn delete_values(&mut self, factoid: &str) -> Result<(), FactoidError> {
+                use self::factoid_store::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
 
-        match count {
-            Ok(c) => Ok(c as i32),
-            Err(diesel::NotFound) => Ok(0),
-            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+                diesel::delete(factoid_store::table.filter(columns::factoid.eq(factoid)))
+                    .execute(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(())
+            }
+
+            fn get_values(&self, factoid: &str) -> Result<Vec<StoredValue>, FactoidError> {
+                use self::factoid_store::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(factoid_store::table
+                    .filter(columns::factoid.eq(factoid))
+                    .load::<StoredValue>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn list_values(&self) -> Result<Vec<StoredValue>, FactoidError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(factoid_store::table
+                    .load::<StoredValue>(conn)
+                    .context(ErrorKind::$error)?)
+            }
         }
-    }
+    };
 }
+
+#[cfg(feature = "mysql")]
+impl_database!(MysqlConnection, MysqlError);
+
+#[cfg(feature = "sqlite")]
+impl_database!(SqliteConnection, SqliteError);
+
+#[cfg(feature = "postgres")]
+impl_database!(PgConnection, PostgresError);
@@ -0,0 +1,192 @@
+//! Changing factoids with substitutions and comparing their versions.
+use std::cmp;
+use std::str::FromStr;
+
+use regex::{Regex, RegexBuilder};
+
+use super::error::*;
+use failure::ResultExt;
+
+/// Comparing more pairs of words than this takes too long,
+/// the changed part is shown as removed and added as a whole then.
+const MAX_COMPARISONS: usize = 250_000;
+
+/// A sed like `s/regex/replacement/flags` expression.
+///
+/// Any punctuation can be used instead of `/`, it can be escaped with `\`.
+/// The flag `g` replaces every match and `i` ignores the case.
+#[derive(Debug)]
+pub struct Substitution {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    pub fn apply(&self, content: &str) -> String {
+        let replaced = if self.global {
+            self.regex.replace_all(content, &self.replacement[..])
+        } else {
+            self.regex.replace(content, &self.replacement[..])
+        };
+
+        replaced.into_owned()
+    }
+}
+
+impl FromStr for Substitution {
+    type Err = FactoidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        if chars.next() != Some('s') {
+            Err(ErrorKind::InvalidSubstitution)?;
+        }
+
+        let delimiter = match chars.next() {
+            Some(c) if c.is_ascii_punctuation() && c != '\\' => c,
+            _ => Err(ErrorKind::InvalidSubstitution)?,
+        };
+
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let part = parts.last_mut().unwrap();
+            if escaped {
+                // Other escapes belong to the regex
+                if c != delimiter {
+                    part.push('\\');
+                }
+                part.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delimiter {
+                parts.push(String::new());
+            } else {
+                part.push(c);
+            }
+        }
+
+        if escaped {
+            parts.last_mut().unwrap().push('\\');
+        }
+
+        // The last delimiter is optional
+        if parts.len() < 2 || parts.len() > 3 {
+            Err(ErrorKind::InvalidSubstitution)?;
+        }
+
+        let flags = parts.get(2).map_or("", String::as_str);
+        if flags.chars().any(|c| c != 'g' && c != 'i') {
+            Err(ErrorKind::InvalidSubstitution)?;
+        }
+
+        let regex = RegexBuilder::new(&parts[0])
+            .case_insensitive(flags.contains('i'))
+            .build()
+            .context(ErrorKind::InvalidSubstitution)?;
+
+        Ok(Substitution {
+            regex,
+            replacement: parts[1].clone(),
+            global: flags.contains('g'),
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Same,
+    Removed,
+    Added,
+}
+
+/// Compares the words of two versions,
+/// removed words are marked with `[-...-]` and added ones with `{+...+}`.
+pub fn diff(old: &str, new: &str) -> String {
+    let old = old.split_whitespace().collect::<Vec<_>>();
+    let new = new.split_whitespace().collect::<Vec<_>>();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+
+    let mut changes = old[..prefix]
+        .iter()
+        .map(|w| (Change::Same, *w))
+        .collect::<Vec<_>>();
+
+    if old_changed.len() * new_changed.len() > MAX_COMPARISONS {
+        changes.extend(old_changed.iter().map(|w| (Change::Removed, *w)));
+        changes.extend(new_changed.iter().map(|w| (Change::Added, *w)));
+    } else {
+        changes.extend(compare(old_changed, new_changed));
+    }
+
+    changes.extend(old[old.len() - suffix..].iter().map(|w| (Change::Same, *w)));
+
+    let mut groups: Vec<(Change, Vec<&str>)> = Vec::new();
+    for (change, word) in changes {
+        match groups.last_mut() {
+            Some((last, words)) if *last == change => words.push(word),
+            _ => groups.push((change, vec![word])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(change, words)| {
+            let words = words.join(" ");
+            match change {
+                Change::Same => words,
+                Change::Removed => format!("[-{}-]", words),
+                Change::Added => format!("{{+{}+}}", words),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Finds the longest common subsequence of words.
+fn compare<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Change, &'a str)> {
+    // lengths[i][j] is the length of the subsequence of old[i..] and new[j..]
+    let mut lengths = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lengths[i + 1][j], lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push((Change::Same, old[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            changes.push((Change::Removed, old[i]));
+            i += 1;
+        } else {
+            changes.push((Change::Added, new[j]));
+            j += 1;
+        }
+    }
+
+    changes.extend(old[i..].iter().map(|w| (Change::Removed, *w)));
+    changes.extend(new[j..].iter().map(|w| (Change::Added, *w)));
+
+    changes
+}
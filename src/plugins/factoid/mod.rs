@@ -7,6 +7,8 @@ use std::time::{Duration, Instant};
 
 use antidote::RwLock;
 use irc::client::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rlua::prelude::*;
 use rlua::HookTriggers;
 
@@ -16,11 +18,14 @@ use time;
 use crate::plugin::*;
 use crate::FrippyClient;
 pub mod database;
-use self::database::Database;
+use self::database::{Database, StoreDatabase};
+
+mod edit;
+use self::edit::{diff, Substitution};
 
 mod utils;
 use self::utils::*;
-use crate::utils::Url;
+use crate::utils::{Http, Pager, Url};
 
 use self::error::*;
 use crate::error::ErrorKind as FrippyErrorKind;
@@ -31,16 +36,33 @@ use frippy_derive::PluginName;
 
 static LUA_SANDBOX: &'static str = include_str!("sandbox.lua");
 
+/// The number of versions shown by `history`.
+const HISTORY_LEN: i32 = 5;
+/// The maximum number of results of `list`, `search` and `by`.
+const MAX_RESULTS: i64 = 100;
+/// The number of names in a line of `list`.
+const NAMES_PER_LINE: usize = 10;
+/// The length of the content shown by `search`.
+const PREVIEW_LEN: usize = 80;
+
 #[derive(PluginName)]
-pub struct Factoid<T: Database, C: Client> {
+pub struct Factoid<T: Database, S: StoreDatabase, C: Client> {
     factoids: RwLock<T>,
+    store: RwLock<S>,
+    http: Http,
+    pager: Pager,
     phantom: PhantomData<C>,
 }
 
-impl<T: Database, C: Client> Factoid<T, C> {
-    pub fn new(db: T) -> Self {
+impl<T: Database, S: StoreDatabase, C: FrippyClient> Factoid<T, S, C> {
+    /// `store` keeps the values which Lua factoids save between runs,
+    /// `fromurl` and factoids download everything through `http`.
+    pub fn new(db: T, store: S, http: Http) -> Self {
         Factoid {
             factoids: RwLock::new(db),
+            store: RwLock::new(store),
+            http,
+            pager: Pager::new(5, "factoids more"),
             phantom: PhantomData,
         }
     }
@@ -54,12 +76,16 @@ impl<T: Database, C: Client> Factoid<T, C> {
         let count = self.factoids.read().count_factoids(name)?;
         let tm = time::now().to_timespec();
 
+        // New versions stay locked
+        let locked = count > 0 && self.factoids.read().get_factoid(name, count - 1)?.locked;
+
         let factoid = database::NewFactoid {
             name,
             idx: count,
             content,
             author,
             created: NaiveDateTime::from_timestamp(tm.sec, 0u32),
+            locked,
         };
 
         Ok(self
@@ -69,6 +95,101 @@ impl<T: Database, C: Client> Factoid<T, C> {
             .map(|()| "Successfully added!")?)
     }
 
+    /// The first author of a factoid owns it.
+    ///
+    /// Only the nick of the author is stored, so anyone using that nick
+    /// while the author is offline can change the factoid as well.
+    fn owner(&self, name: &str) -> Result<String, FactoidError> {
+        Ok(self.factoids.read().get_factoid(name, 0)?.author)
+    }
+
+    /// Owners of the bot may change every factoid, others may not change
+    /// protected names and only the owner of a locked factoid may change it.
+    ///
+    /// Owners of the bot are recognized by their hostmask since anyone can take a nick.
+    fn check_access(
+        &self,
+        client: &C,
+        name: &str,
+        command: &PluginCommand,
+    ) -> Result<(), FactoidError> {
+        if client.is_owner_mask(&command.prefix) {
+            return Ok(());
+        }
+
+        if is_protected(client.config(), name) {
+            Err(ErrorKind::Protected)?;
+        }
+
+        let latest = match self.latest(name) {
+            Ok(factoid) => factoid,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if latest.locked && !self.owner(name)?.eq_ignore_ascii_case(&command.source) {
+            Err(ErrorKind::Locked)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a subcommand which changes the factoid named by the first token.
+    fn change(
+        &self,
+        client: &C,
+        sub_command: &str,
+        command: &mut PluginCommand,
+    ) -> Result<String, FactoidError> {
+        let name = match command.tokens.get(0) {
+            Some(name) => name.clone(),
+            None => Err(ErrorKind::InvalidCommand)?,
+        };
+
+        self.check_access(client, &name, command)?;
+
+        let message = match sub_command {
+            "add" => self.add(command)?,
+            "fromurl" => self.add_from_url(command)?,
+            "edit" => self.edit(command)?,
+            "remove" => self.remove(command)?,
+            "revert" => self.revert(command)?,
+            _ => Err(ErrorKind::InvalidCommand)?,
+        };
+
+        Ok(message.to_owned())
+    }
+
+    fn set_locked(
+        &self,
+        client: &C,
+        command: &PluginCommand,
+        locked: bool,
+    ) -> Result<&str, FactoidError> {
+        let name = match command.tokens.get(0) {
+            Some(name) => name,
+            None => Err(ErrorKind::InvalidCommand)?,
+        };
+
+        if !client.is_owner_mask(&command.prefix) {
+            if is_protected(client.config(), name) {
+                Err(ErrorKind::Protected)?;
+            }
+
+            if !self.owner(name)?.eq_ignore_ascii_case(&command.source) {
+                Err(ErrorKind::NotOwner)?;
+            }
+        }
+
+        self.factoids.write().set_locked(name, locked)?;
+
+        Ok(if locked {
+            "Successfully locked!"
+        } else {
+            "Successfully unlocked!"
+        })
+    }
+
     fn add(&self, command: &mut PluginCommand) -> Result<&str, FactoidError> {
         if command.tokens.len() < 2 {
             Err(ErrorKind::InvalidCommand)?;
@@ -89,6 +210,7 @@ impl<T: Database, C: Client> Factoid<T, C> {
         let url = &command.tokens[0];
         let content = Url::from(url.as_ref())
             .max_kib(1024)
+            .http(&self.http)
             .request()
             .context(ErrorKind::Download)?;
 
@@ -103,9 +225,209 @@ impl<T: Database, C: Client> Factoid<T, C> {
         let name = command.tokens.remove(0);
         let count = self.factoids.read().count_factoids(&name)?;
 
-        match self.factoids.write().delete_factoid(&name, count - 1) {
-            Ok(()) => Ok("Successfully removed"),
-            Err(e) => Err(e)?,
+        self.factoids.write().delete_factoid(&name, count - 1)?;
+
+        // A new factoid with the same name should not see the old values
+        if count == 1 {
+            self.store.write().delete_values(&name)?;
+        }
+
+        Ok("Successfully removed")
+    }
+
+    fn latest(&self, name: &str) -> Result<database::Factoid, FactoidError> {
+        let count = self.factoids.read().count_factoids(name)?;
+        if count < 1 {
+            Err(ErrorKind::NotFound)?;
+        }
+
+        self.factoids.read().get_factoid(name, count - 1)
+    }
+
+    fn edit(&self, command: &mut PluginCommand) -> Result<&str, FactoidError> {
+        if command.tokens.len() < 2 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let name = command.tokens.remove(0);
+        let substitution = command.tokens.join(" ").parse::<Substitution>()?;
+        let latest = self.latest(&name)?;
+
+        let content = substitution.apply(&latest.content);
+        if content == latest.content {
+            Err(ErrorKind::Unchanged)?;
+        }
+
+        self.create_factoid(&name, &content, &command.source)?;
+        Ok("Successfully edited!")
+    }
+
+    fn history(&self, command: &PluginCommand) -> Result<String, FactoidError> {
+        let name = match command.tokens.get(0) {
+            Some(name) => name,
+            None => Err(ErrorKind::InvalidCommand)?,
+        };
+
+        // Starts at the latest or the given version
+        let end = match command.tokens.get(1) {
+            Some(idx) => i32::from_str(idx)
+                .context(ErrorKind::InvalidIndex)?
+                .saturating_add(1),
+            None => self.factoids.read().count_factoids(name)?,
+        };
+
+        let versions =
+            self.factoids
+                .read()
+                .get_factoids(name, end.saturating_sub(HISTORY_LEN), end)?;
+
+        let oldest = match versions.first() {
+            Some(factoid) => factoid.idx,
+            None => Err(ErrorKind::NotFound)?,
+        };
+
+        let mut lines = versions
+            .iter()
+            .rev()
+            .map(|f| {
+                format!(
+                    "{} {}: Added by {} at {} UTC",
+                    f.name, f.idx, f.author, f.created
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if oldest > 0 {
+            lines.push(format!(
+                "{} older versions, use \"history {} {}\" to see them",
+                oldest,
+                name,
+                oldest - 1
+            ));
+        }
+
+        Ok(lines.join("\r\n"))
+    }
+
+    fn diff(&self, command: &PluginCommand) -> Result<String, FactoidError> {
+        if command.tokens.len() < 3 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let name = &command.tokens[0];
+        let old = i32::from_str(&command.tokens[1]).context(ErrorKind::InvalidIndex)?;
+        let new = i32::from_str(&command.tokens[2]).context(ErrorKind::InvalidIndex)?;
+
+        let old_factoid = self.factoids.read().get_factoid(name, old)?;
+        let new_factoid = self.factoids.read().get_factoid(name, new)?;
+
+        if old_factoid.content == new_factoid.content {
+            return Ok(format!(
+                "Versions {} and {} of {} are identical",
+                old, new, name
+            ));
+        }
+
+        let mut message = format!(
+            "{} {}..{}: {}",
+            name,
+            old,
+            new,
+            diff(&old_factoid.content, &new_factoid.content)
+        );
+        truncate(&mut message, 512);
+
+        Ok(message)
+    }
+
+    fn revert(&self, command: &mut PluginCommand) -> Result<&str, FactoidError> {
+        if command.tokens.len() < 2 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let name = &command.tokens[0];
+        let idx = i32::from_str(&command.tokens[1]).context(ErrorKind::InvalidIndex)?;
+
+        let old = self.factoids.read().get_factoid(name, idx)?;
+        let latest = self.latest(name)?;
+        if old.content == latest.content {
+            Err(ErrorKind::Unchanged)?;
+        }
+
+        self.create_factoid(name, &old.content, &command.source)?;
+        Ok("Successfully reverted!")
+    }
+
+    fn list(&self, command: &PluginCommand) -> Result<String, FactoidError> {
+        let prefix = command.tokens.get(0).map_or("", String::as_str);
+        let names = self.factoids.read().list_names(prefix, MAX_RESULTS)?;
+
+        let lines = names.chunks(NAMES_PER_LINE).map(|names| names.join(", "));
+
+        Ok(self.start_pages(&command.source, lines, "No factoids found."))
+    }
+
+    fn search(&self, command: &PluginCommand) -> Result<String, FactoidError> {
+        let query = command.tokens.join(" ");
+        if query.is_empty() {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let factoids = self.factoids.read().search_factoids(&query, MAX_RESULTS)?;
+
+        // Only the newest matching version of each factoid is shown
+        let mut lines = Vec::new();
+        let mut last_name = None;
+        for factoid in &factoids {
+            if last_name == Some(&factoid.name) {
+                continue;
+            }
+            last_name = Some(&factoid.name);
+
+            let mut preview = factoid.content.replace("\n", "|").replace("\r", "");
+            if preview.len() > PREVIEW_LEN {
+                truncate(&mut preview, PREVIEW_LEN);
+                preview.push_str("...");
+            }
+
+            lines.push(format!("{}: {}", factoid.name, preview));
+        }
+
+        Ok(self.start_pages(&command.source, lines, "No matching factoids found."))
+    }
+
+    fn by(&self, command: &PluginCommand) -> Result<String, FactoidError> {
+        let author = match command.tokens.get(0) {
+            Some(author) => author,
+            None => Err(ErrorKind::InvalidCommand)?,
+        };
+
+        let factoids = self.factoids.read().get_factoids_by(author, MAX_RESULTS)?;
+        let lines = factoids
+            .into_iter()
+            .map(|f| format!("{} {}: Added at {} UTC", f.name, f.idx, f.created));
+
+        Ok(self.start_pages(&command.source, lines, "No factoids found."))
+    }
+
+    fn more(&self, command: &PluginCommand) -> String {
+        let page = self.pager.next(&command.source);
+        if page.is_empty() {
+            String::from("There is nothing left to show.")
+        } else {
+            page.join("\r\n")
+        }
+    }
+
+    fn start_pages<I>(&self, key: &str, lines: I, empty: &str) -> String
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let page = self.pager.start(key, lines);
+        if page.is_empty() {
+            empty.to_owned()
+        } else {
+            page.join("\r\n")
         }
     }
 
@@ -152,11 +474,21 @@ impl<T: Database, C: Client> Factoid<T, C> {
                 let name = &command.tokens[0];
                 let count = self.factoids.read().count_factoids(name)?;
 
-                Ok(match count {
+                let versions = match count {
                     0 => Err(ErrorKind::NotFound)?,
                     1 => format!("There is 1 version of {}", name),
                     _ => format!("There are {} versions of {}", count, name),
-                })
+                };
+
+                let latest = self.factoids.read().get_factoid(name, count - 1)?;
+                let lock = if latest.locked { ", it is locked" } else { "" };
+
+                Ok(format!(
+                    "{} owned by {}{}",
+                    versions,
+                    self.owner(name)?,
+                    lock
+                ))
             }
             _ => {
                 let name = &command.tokens[0];
@@ -244,28 +576,113 @@ impl<T: Database, C: Client> Factoid<T, C> {
             },
         );
 
+        let http = self.http.clone();
+        let post_http = self.http.clone();
+        let mut rng = StdRng::from_entropy();
         let output = lua.context(|ctx| {
-            let globals = ctx.globals();
-
-            globals.set("factoid", code)?;
-            globals.set(
-                "download",
-                ctx.create_function(|ctx, url| download(&ctx, url))?,
-            )?;
-            globals.set(
-                "json_decode",
-                ctx.create_function(|ctx, json| json_decode(&ctx, json))?,
-            )?;
-            globals.set("sleep", ctx.create_function(|ctx, ms| sleep(&ctx, ms))?)?;
-            globals.set("args", args)?;
-            globals.set("input", command.tokens.join(" "))?;
-            globals.set("user", command.source.clone())?;
-            globals.set("channel", command.target.clone())?;
-            globals.set("output", ctx.create_table()?)?;
-
-            ctx.load(LUA_SANDBOX).set_name(name)?.exec()?;
-
-            Ok(globals.get::<_, Vec<String>>("output")?)
+            // Scoped functions can use the store without owning it
+            ctx.scope(|scope| -> Result<Vec<String>, LuaError> {
+                let globals = ctx.globals();
+
+                globals.set("factoid", code)?;
+                globals.set("factoid_name", name)?;
+                globals.set(
+                    "download",
+                    ctx.create_function(move |ctx, url| download(&ctx, &http, url))?,
+                )?;
+                globals.set(
+                    "http_post",
+                    ctx.create_function(move |ctx, args| post(&ctx, &post_http, args))?,
+                )?;
+                globals.set(
+                    "json_decode",
+                    ctx.create_function(|ctx, json| json_decode(&ctx, json))?,
+                )?;
+                globals.set(
+                    "json_encode",
+                    ctx.create_function(|ctx, value| json_encode(&ctx, value))?,
+                )?;
+                globals.set(
+                    "regex_is_match",
+                    ctx.create_function(|ctx, args| regex_is_match(&ctx, args))?,
+                )?;
+                globals.set(
+                    "regex_match",
+                    ctx.create_function(|ctx, args| regex_match(&ctx, args))?,
+                )?;
+                globals.set(
+                    "regex_find_all",
+                    ctx.create_function(|ctx, args| regex_find_all(&ctx, args))?,
+                )?;
+                globals.set(
+                    "regex_replace",
+                    ctx.create_function(|ctx, args| regex_replace(&ctx, args))?,
+                )?;
+                globals.set(
+                    "regex_split",
+                    ctx.create_function(|ctx, args| regex_split(&ctx, args))?,
+                )?;
+                globals.set(
+                    "url_encode",
+                    ctx.create_function(|ctx, text| url_encode(&ctx, text))?,
+                )?;
+                globals.set(
+                    "url_decode",
+                    ctx.create_function(|ctx, text| url_decode(&ctx, text))?,
+                )?;
+                globals.set(
+                    "html_unescape",
+                    ctx.create_function(|ctx, text| html_unescape(&ctx, text))?,
+                )?;
+                globals.set(
+                    "random",
+                    ctx.create_function_mut(move |_, args| random(&mut rng, args))?,
+                )?;
+                globals.set("date", ctx.create_function(|ctx, args| date(&ctx, args))?)?;
+                globals.set(
+                    "factoid_content",
+                    scope.create_function(|_, name: String| match self.latest(&name) {
+                        Ok(factoid) => Ok(Some(factoid.content)),
+                        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                        Err(e) => Err(LuaError::RuntimeError(e.to_string())),
+                    })?,
+                )?;
+                globals.set(
+                    "store_get",
+                    scope.create_function(|ctx, (factoid, key): (String, String)| {
+                        store_get(&ctx, &self.store, &factoid, key)
+                    })?,
+                )?;
+                globals.set(
+                    "store_set",
+                    scope.create_function(
+                        |_, (factoid, key, value): (String, String, LuaValue)| {
+                            store_set(&self.store, &factoid, key, value)
+                        },
+                    )?,
+                )?;
+                globals.set(
+                    "store_del",
+                    scope.create_function(|_, (factoid, key): (String, String)| {
+                        store_del(&self.store, &factoid, key)
+                    })?,
+                )?;
+                globals.set(
+                    "store_keys",
+                    scope
+                        .create_function(|_, factoid: String| store_keys(&self.store, &factoid))?,
+                )?;
+                globals.set("sleep", ctx.create_function(|ctx, ms| sleep(&ctx, ms))?)?;
+                globals.set("args", args)?;
+                globals.set("input", command.tokens.join(" "))?;
+                globals.set("user", command.source.clone())?;
+                globals.set("channel", command.target.clone())?;
+                globals.set("output", ctx.create_table()?)?;
+
+                ctx.load(LUA_SANDBOX).set_name(name)?.exec()?;
+
+                Ok(globals.get::<_, Vec<String>>("output")?)
+            })
         })?;
 
         Ok(output.join("|"))
@@ -273,11 +690,37 @@ impl<T: Database, C: Client> Factoid<T, C> {
 
     fn help(&self) -> &str {
         "usage: factoids <subcommand>\r\n\
-         subcommands: add, fromurl, remove, get, info, exec, help"
+         subcommands: add, fromurl, edit, remove, get, info, history, diff, revert, \
+         list, search, by, more, lock, unlock, exec, help\r\n\
+         edit takes a substitution like s/old/new/ which may end with g and i"
     }
 }
 
-impl<T: Database, C: FrippyClient> Plugin for Factoid<T, C> {
+/// Checks if `name` is listed in the factoid_protected_names option.
+fn is_protected(config: &Config, name: &str) -> bool {
+    config
+        .options
+        .as_ref()
+        .and_then(|o| o.get("factoid_protected_names"))
+        .map_or(false, |names| {
+            names
+                .split(',')
+                .any(|n| n.trim().eq_ignore_ascii_case(name))
+        })
+}
+
+/// Shortens `message` to at most `max` bytes without splitting a character.
+fn truncate(message: &mut String, max: usize) {
+    if message.len() > max {
+        let end = (0..=max)
+            .rev()
+            .find(|&i| message.is_char_boundary(i))
+            .unwrap_or(0);
+        message.truncate(end);
+    }
+}
+
+impl<T: Database, S: StoreDatabase, C: FrippyClient> Plugin for Factoid<T, S, C> {
     type Client = C;
     fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
         match message.command {
@@ -304,6 +747,7 @@ impl<T: Database, C: FrippyClient> Plugin for Factoid<T, C> {
 
             let c = PluginCommand {
                 source: message.source_nickname().unwrap().to_owned(),
+                prefix: message.prefix.clone().unwrap_or_default(),
                 target: message.response_target().unwrap().to_owned(),
                 tokens: t,
             };
@@ -335,11 +779,23 @@ impl<T: Database, C: FrippyClient> Plugin for Factoid<T, C> {
 
         let sub_command = command.tokens.remove(0);
         let result = match sub_command.as_ref() {
-            "add" => self.add(&mut command).map(|s| s.to_owned()),
-            "fromurl" => self.add_from_url(&mut command).map(|s| s.to_owned()),
-            "remove" => self.remove(&mut command).map(|s| s.to_owned()),
+            "add" | "fromurl" | "edit" | "remove" | "revert" => {
+                self.change(client, &sub_command, &mut command)
+            }
+            "lock" => self
+                .set_locked(client, &command, true)
+                .map(|s| s.to_owned()),
+            "unlock" => self
+                .set_locked(client, &command, false)
+                .map(|s| s.to_owned()),
             "get" => self.get(&command),
             "info" => self.info(&command),
+            "history" => self.history(&command),
+            "diff" => self.diff(&command),
+            "list" => self.list(&command),
+            "search" => self.search(&command),
+            "by" => self.by(&command),
+            "more" => Ok(self.more(&command)),
             "exec" => self.exec(command),
             "help" => Ok(self.help().to_owned()),
             _ => Err(ErrorKind::InvalidCommand.into()),
@@ -370,7 +826,7 @@ impl<T: Database, C: FrippyClient> Plugin for Factoid<T, C> {
     }
 }
 
-impl<T: Database, C: FrippyClient> fmt::Debug for Factoid<T, C> {
+impl<T: Database, S: StoreDatabase, C: FrippyClient> fmt::Debug for Factoid<T, S, C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Factoid {{ ... }}")
     }
@@ -399,17 +855,51 @@ pub mod error {
         #[fail(display = "Entry already exists")]
         Duplicate,
 
+        /// Invalid substitution error
+        #[fail(display = "Invalid substitution, use s/old/new/")]
+        InvalidSubstitution,
+
+        /// Unchanged error
+        #[fail(display = "The factoid would not change")]
+        Unchanged,
+
+        /// Locked error
+        #[fail(display = "Only the owner may change this factoid")]
+        Locked,
+
+        /// Protected error
+        #[fail(display = "Only bot owners may change this factoid")]
+        Protected,
+
+        /// Not owner error
+        #[fail(display = "Only the owner may lock or unlock this factoid")]
+        NotOwner,
+
         /// Not found error
         #[fail(display = "Factoid was not found")]
         NotFound,
 
+        /// File error
+        #[fail(display = "Failed to save to the data file")]
+        FileError,
+
         /// MySQL error
         #[cfg(feature = "mysql")]
         #[fail(display = "Failed to execute MySQL Query")]
         MysqlError,
 
+        /// SQLite error
+        #[cfg(feature = "sqlite")]
+        #[fail(display = "Failed to execute SQLite Query")]
+        SqliteError,
+
+        /// PostgreSQL error
+        #[cfg(feature = "postgres")]
+        #[fail(display = "Failed to execute PostgreSQL Query")]
+        PostgresError,
+
         /// No connection error
-        #[cfg(feature = "mysql")]
+        #[cfg(feature = "sql")]
         #[fail(display = "No connection to the database")]
         NoConnection,
     }
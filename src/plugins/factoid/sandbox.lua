@@ -1,16 +1,21 @@
-function send(text)
-  local text = tostring(text)
-  local len = #output
-  if len < 1 then
-    output = { text }
-  else
-    output[len] = output[len] .. text
+-- Factoids called with factoid() write into their own buffer
+local function writer(buffer)
+  local function send(text)
+    local text = tostring(text)
+    local len = #buffer
+    if len < 1 then
+      buffer[1] = text
+    else
+      buffer[len] = buffer[len] .. text
+    end
+  end
+
+  local function sendln(text)
+    send(text)
+    table.insert(buffer, "")
   end
-end
 
-function sendln(text)
-  send(text)
-  table.insert(output, "")
+  return send, sendln
 end
 
 function trim(s)
@@ -18,30 +23,61 @@ function trim(s)
   return from > #s and "" or s:match(".*%S", from)
 end
 
-trimmedInput = trim(input)
+local function input_or_user(input)
+  local trimmed = trim(input)
+  if trimmed == "" then
+    return user
+  else
+    return trimmed
+  end
+end
 
-if trimmedInput == "" then
-  ioru = user
-else
-  ioru = trimmedInput
+-- Every factoid only sees its own keys
+local function make_store(name)
+  return {
+    get = function(key) return store_get(name, key) end,
+    set = function(key, value) return store_set(name, key, value) end,
+    del = function(key) return store_del(name, key) end,
+    keys = function() return store_keys(name) end
+  }
 end
 
-local sandbox_env = {
-  print = send,
-  println = sendln,
+-- Tables are copied into every environment so factoids can not change
+-- the libraries of the factoids they call or which call them
+local libraries = {
+  json = {decode = json_decode, encode = json_encode},
+  http = {get = download, post = http_post},
+  regex = {
+    is_match = regex_is_match,
+    match = regex_match,
+    find_all = regex_find_all,
+    replace = regex_replace,
+    split = regex_split
+  },
+  url = {encode = url_encode, decode = url_decode},
+  html = {unescape = html_unescape},
+  os = {
+    date = date,
+    time = os.time,
+    difftime = os.difftime
+  },
+  string = string,
+  math = math,
+  table = table,
+  utf8 = utf8
+}
+
+string.rep = nil
+string.dump = nil
+math.randomseed = nil
+-- Seeded for every run instead of sharing the generator of the C library
+math.random = random
+
+local base_env = {
   trim = trim,
-  eval = nil,
-  sleep = nil,
-  json = {decode = json_decode},
-  args = args,
-  input = input,
   user = user,
-  ioru = ioru,
   channel = channel,
   request = download,
-  string = string,
-  math = math,
-  table = table,
   pairs = pairs,
   ipairs = ipairs,
   next = next,
@@ -57,24 +93,12 @@ local sandbox_env = {
   _VERSION = _VERSION
 }
 
-sandbox_env.os = {
-  date = os.date,
-  time = os.time,
-  difftime = os.difftime
-}
-
-sandbox_env.string.rep = nil
-sandbox_env.string.dump = nil
-sandbox_env.math.randomseed = nil
-
--- Temporary evaluation function
-function eval(code)
-  local c, e = load(code, nil, nil, sandbox_env)
-  if c then
-    return c()
-  else
-    error(e)
+local function copy(t)
+  local c = {}
+  for k, v in pairs(t) do
+    c[k] = v
   end
+  return c
 end
 
 -- Only sleeps for 1 second at a time
@@ -87,10 +111,102 @@ function safesleep(dur)
   sleep(dur)
 end
 
-sandbox_env.eval = eval
-sandbox_env.sleep = safesleep
+-- Builds the environment of a factoid which shares no tables with the others
+local function make_env(name, buffer, call_args, call_input)
+  local env = copy(base_env)
+  for k, v in pairs(libraries) do
+    env[k] = copy(v)
+  end
+
+  env.print, env.println = writer(buffer)
+  env.store = make_store(name)
+  env.args = call_args
+  env.input = call_input
+  env.ioru = input_or_user(call_input)
+  env.sleep = safesleep
+  env.factoid = call_factoid
+
+  -- Evaluates code in the environment of the factoid which calls it
+  env.eval = function(code)
+    local c, e = load(code, nil, "t", env)
+    if c then
+      return c()
+    else
+      error(e)
+    end
+  end
+
+  return env
+end
+
+-- Calls can not be nested deeper than this
+local MAX_DEPTH = 8
+local call_stack = { factoid_name }
+-- Errors which already contain the call chain
+local chained_errors = {}
+
+local function call_chain(name)
+  return table.concat(call_stack, " -> ") .. " -> " .. name
+end
+
+-- Lua factoids return what they print, other factoids their content
+function call_factoid(name, ...)
+  local name = tostring(name)
+  for _, caller in ipairs(call_stack) do
+    if caller == name then
+      error("factoid cycle: " .. call_chain(name), 2)
+    end
+  end
+
+  if #call_stack >= MAX_DEPTH then
+    error("factoids are nested too deep: " .. call_chain(name), 2)
+  end
+
+  local content = factoid_content(name)
+  if content == nil then
+    error("factoid not found: " .. call_chain(name), 2)
+  end
+
+  if content:sub(1, 1) ~= ">" then
+    return content
+  end
+
+  content = content:sub(2)
+  if content:sub(1, 1) == ">" then
+    return content
+  end
+
+  local call_args = {}
+  for i = 1, select("#", ...) do
+    call_args[i] = tostring((select(i, ...)))
+  end
+
+  local buffer = {}
+  local env = make_env(name, buffer, call_args, table.concat(call_args, " "))
+
+  local f, e = load(content, "=" .. name, "t", env)
+  if not f then
+    error("in " .. call_chain(name) .. ": " .. e, 2)
+  end
+
+  table.insert(call_stack, name)
+  local ok, err = pcall(f)
+  table.remove(call_stack)
+
+  if not ok then
+    if type(err) == "string" and chained_errors[err] then
+      error(err, 0)
+    end
+
+    local message = "in " .. call_chain(name) .. ": " .. tostring(err)
+    chained_errors[message] = true
+    error(message, 0)
+  end
+
+  return table.concat(buffer, "\n")
+end
 
-local f, e = load(factoid, nil, nil, sandbox_env)
+local f, e = load(factoid, nil, "t", make_env(factoid_name, output, args, input))
 
 if f then
   f()
@@ -1,39 +1,74 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
 use std::thread;
 use std::time::Duration;
 
-use serde_json::{self, Value as SerdeValue};
+use antidote::RwLock;
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::Rng;
+use regex::{Captures, Regex, RegexBuilder};
+use serde_json::{self, Map, Number, Value as SerdeValue};
+use url::form_urlencoded;
+use url::percent_encoding::percent_decode;
 
 use rlua::Error as LuaError;
 use rlua::Error::RuntimeError;
-use rlua::{Context, Value as LuaValue};
+use rlua::{Context, Value as LuaValue, Variadic};
 
+use super::database::{NewStoredValue, StoreDatabase};
+use super::error::ErrorKind as FactoidErrorKind;
+use crate::utils::error::DownloadError;
 use crate::utils::error::ErrorKind::Connection;
-use crate::utils::Url;
+use crate::utils::{Http, Url};
 
 use failure::Fail;
 
+/// The maximum number of keys in the store of a factoid.
+const MAX_STORE_KEYS: usize = 100;
+/// The maximum size of all keys and values in the store of a factoid.
+const MAX_STORE_BYTES: usize = 64 * 1024;
+/// The maximum length of a key, it has to fit into the column.
+const MAX_KEY_LEN: usize = 64;
+/// Longer formats could make os.date allocate a lot of memory outside of Lua.
+const MAX_DATE_FORMAT_LEN: usize = 256;
+/// The maximum size of compiled regexes.
+const MAX_REGEX_SIZE: usize = 1 << 20;
+/// The maximum length of the result of regex.replace.
+const MAX_REPLACED_LEN: usize = 1024 * 1024;
+
 pub fn sleep(_: &Context, dur: u64) -> Result<(), LuaError> {
     thread::sleep(Duration::from_millis(dur));
     Ok(())
 }
 
-pub fn download(_: &Context, url: String) -> Result<String, LuaError> {
-    let url = Url::from(url).max_kib(1024);
-    match url.request() {
-        Ok(v) => Ok(v),
-        Err(e) => {
-            let error = match e.kind() {
-                Connection => e.cause().unwrap().to_string(),
-                _ => e.to_string(),
-            };
-
-            Err(RuntimeError(format!(
-                "Failed to download {} - {}",
-                url.as_str(),
-                error
-            )))
-        }
+pub fn download(_: &Context, http: &Http, url: String) -> Result<String, LuaError> {
+    let url = Url::from(url).max_kib(1024).http(http);
+    url.request().map_err(|e| request_error(url.as_str(), &e))
+}
+
+pub fn post(
+    _: &Context,
+    http: &Http,
+    (url, body, headers): (String, String, Option<HashMap<String, String>>),
+) -> Result<String, LuaError> {
+    let mut url = Url::from(url).max_kib(1024).http(http).post(body);
+    for (name, value) in headers.unwrap_or_default() {
+        url = url.header(&name, &value);
     }
+
+    url.request().map_err(|e| request_error(url.as_str(), &e))
+}
+
+fn request_error(url: &str, e: &DownloadError) -> LuaError {
+    let error = match e.kind() {
+        Connection => e.cause().unwrap().to_string(),
+        _ => e.to_string(),
+    };
+
+    RuntimeError(format!("Failed to download {} - {}", url, error))
 }
 
 fn convert_value<'l>(
@@ -84,3 +119,370 @@ pub fn json_decode<'l>(lua: &Context<'l>, json: String) -> Result<LuaValue<'l>,
 
     convert_value(lua, ser_val, 25)
 }
+
+fn convert_lua_value(value: LuaValue, max_recurs: usize) -> Result<SerdeValue, LuaError> {
+    if max_recurs == 0 {
+        return Err(RuntimeError(String::from(
+            "Reached max recursion level - table is nested too deep",
+        )));
+    }
+
+    let sval =
+        match value {
+            LuaValue::Nil => SerdeValue::Null,
+            LuaValue::Boolean(b) => SerdeValue::Bool(b),
+            LuaValue::Integer(i) => SerdeValue::Number(Number::from(i)),
+            LuaValue::Number(n) => SerdeValue::Number(Number::from_f64(n).ok_or_else(|| {
+                RuntimeError(String::from("NaN and infinity can not be converted"))
+            })?),
+            LuaValue::String(s) => SerdeValue::String(s.to_str()?.to_owned()),
+            LuaValue::Table(table) => {
+                let len = table.raw_len();
+                let pairs = table
+                    .clone()
+                    .pairs::<LuaValue, LuaValue>()
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                // Sequences become arrays, everything else an object
+                if len > 0 && pairs.len() as i64 == len {
+                    let mut arr = Vec::with_capacity(pairs.len());
+                    for i in 1..=len {
+                        arr.push(convert_lua_value(table.raw_get(i)?, max_recurs - 1)?);
+                    }
+
+                    SerdeValue::Array(arr)
+                } else {
+                    let mut obj = Map::new();
+                    for (key, val) in pairs {
+                        let key = match key {
+                            LuaValue::String(s) => s.to_str()?.to_owned(),
+                            LuaValue::Integer(i) => i.to_string(),
+                            LuaValue::Number(n) => n.to_string(),
+                            _ => {
+                                return Err(RuntimeError(String::from(
+                                    "Only strings and numbers can be used as keys",
+                                )))
+                            }
+                        };
+
+                        obj.insert(key, convert_lua_value(val, max_recurs - 1)?);
+                    }
+
+                    SerdeValue::Object(obj)
+                }
+            }
+            _ => {
+                return Err(RuntimeError(format!(
+                    "Values of type {} can not be converted",
+                    value.type_name()
+                )))
+            }
+        };
+
+    Ok(sval)
+}
+
+pub fn json_encode(_: &Context, value: LuaValue) -> Result<String, LuaError> {
+    Ok(convert_lua_value(value, 25)?.to_string())
+}
+
+/// Only strings, numbers, booleans and tables of them can be stored.
+pub fn store_set<S: StoreDatabase>(
+    store: &RwLock<S>,
+    factoid: &str,
+    key: String,
+    value: LuaValue,
+) -> Result<(), LuaError> {
+    if let LuaValue::Nil = value {
+        return store_del(store, factoid, key);
+    }
+
+    if key.len() > MAX_KEY_LEN {
+        return Err(RuntimeError(format!(
+            "Keys can not be longer than {} bytes",
+            MAX_KEY_LEN
+        )));
+    }
+
+    let value = convert_lua_value(value, 25)?.to_string();
+
+    // Locked so other runs of the factoid can not exceed the quota at the same time
+    let mut store = store.write();
+    let others = store
+        .get_values(factoid)
+        .map_err(|e| RuntimeError(e.to_string()))?
+        .into_iter()
+        .filter(|v| v.store_key != key)
+        .collect::<Vec<_>>();
+
+    let used = others
+        .iter()
+        .map(|v| v.store_key.len() + v.value.len())
+        .sum::<usize>();
+
+    if others.len() >= MAX_STORE_KEYS {
+        return Err(RuntimeError(format!(
+            "The store can not have more than {} keys",
+            MAX_STORE_KEYS
+        )));
+    }
+
+    if used + key.len() + value.len() > MAX_STORE_BYTES {
+        return Err(RuntimeError(format!(
+            "The store can not be larger than {} KiB",
+            MAX_STORE_BYTES / 1024
+        )));
+    }
+
+    let value = NewStoredValue {
+        factoid,
+        store_key: &key,
+        value: &value,
+    };
+
+    store
+        .set_value(&value)
+        .map_err(|e| RuntimeError(e.to_string()))
+}
+
+pub fn store_get<'l, S: StoreDatabase>(
+    lua: &Context<'l>,
+    store: &RwLock<S>,
+    factoid: &str,
+    key: String,
+) -> Result<LuaValue<'l>, LuaError> {
+    let value = match store.read().get_value(factoid, &key) {
+        Ok(value) => value,
+        Err(ref e) if e.kind() == FactoidErrorKind::NotFound => return Ok(LuaValue::Nil),
+        Err(e) => return Err(RuntimeError(e.to_string())),
+    };
+
+    let sval = serde_json::from_str(&value.value).map_err(|e| RuntimeError(e.to_string()))?;
+    convert_value(lua, sval, 25)
+}
+
+pub fn store_del<S: StoreDatabase>(
+    store: &RwLock<S>,
+    factoid: &str,
+    key: String,
+) -> Result<(), LuaError> {
+    match store.write().delete_value(factoid, &key) {
+        Err(ref e) if e.kind() != FactoidErrorKind::NotFound => Err(RuntimeError(e.to_string())),
+        _ => Ok(()),
+    }
+}
+
+pub fn store_keys<S: StoreDatabase>(
+    store: &RwLock<S>,
+    factoid: &str,
+) -> Result<Vec<String>, LuaError> {
+    let mut keys = store
+        .read()
+        .get_values(factoid)
+        .map_err(|e| RuntimeError(e.to_string()))?
+        .into_iter()
+        .map(|v| v.store_key)
+        .collect::<Vec<_>>();
+
+    keys.sort();
+    Ok(keys)
+}
+
+fn build_regex(pattern: &str) -> Result<Regex, LuaError> {
+    RegexBuilder::new(pattern)
+        .size_limit(MAX_REGEX_SIZE)
+        .dfa_size_limit(MAX_REGEX_SIZE)
+        .build()
+        .map_err(|e| RuntimeError(format!("Invalid regex: {}", e)))
+}
+
+pub fn regex_is_match(_: &Context, (pattern, text): (String, String)) -> Result<bool, LuaError> {
+    Ok(build_regex(&pattern)?.is_match(&text))
+}
+
+/// Returns the groups of the first match like string.match,
+/// the whole match is returned if the regex has no groups.
+pub fn regex_match(
+    _: &Context,
+    (pattern, text): (String, String),
+) -> Result<Variadic<Option<String>>, LuaError> {
+    let re = build_regex(&pattern)?;
+    let caps = match re.captures(&text) {
+        Some(caps) => caps,
+        None => return Ok(Variadic::new()),
+    };
+
+    if caps.len() == 1 {
+        return Ok(caps
+            .get(0)
+            .map(|m| m.as_str().to_owned())
+            .into_iter()
+            .collect());
+    }
+
+    Ok(caps
+        .iter()
+        .skip(1)
+        .map(|m| m.map(|m| m.as_str().to_owned()))
+        .collect())
+}
+
+pub fn regex_find_all(
+    _: &Context,
+    (pattern, text): (String, String),
+) -> Result<Vec<String>, LuaError> {
+    Ok(build_regex(&pattern)?
+        .find_iter(&text)
+        .map(|m| m.as_str().to_owned())
+        .collect())
+}
+
+/// Replaces every match, `$1` and `${name}` in the replacement refer to groups.
+pub fn regex_replace(
+    _: &Context,
+    (pattern, text, replacement): (String, String, String),
+) -> Result<String, LuaError> {
+    let re = build_regex(&pattern)?;
+
+    // Expanding stops once the result gets too long
+    let mut len = text.len();
+    let replaced = re.replace_all(&text, |caps: &Captures| {
+        let mut expanded = String::new();
+        if len <= MAX_REPLACED_LEN {
+            caps.expand(&replacement, &mut expanded);
+            len += expanded.len();
+        }
+
+        expanded
+    });
+
+    if len > MAX_REPLACED_LEN {
+        return Err(RuntimeError(format!(
+            "The result can not be longer than {} KiB",
+            MAX_REPLACED_LEN / 1024
+        )));
+    }
+
+    Ok(replaced.into_owned())
+}
+
+pub fn regex_split(
+    _: &Context,
+    (pattern, text): (String, String),
+) -> Result<Vec<String>, LuaError> {
+    Ok(build_regex(&pattern)?
+        .split(&text)
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// Encodes a query parameter, spaces become `+`.
+pub fn url_encode(_: &Context, text: String) -> Result<String, LuaError> {
+    Ok(form_urlencoded::byte_serialize(text.as_bytes()).collect())
+}
+
+pub fn url_decode(_: &Context, text: String) -> Result<String, LuaError> {
+    let text = text.replace('+', " ");
+    Ok(percent_decode(text.as_bytes())
+        .decode_utf8_lossy()
+        .into_owned())
+}
+
+pub fn html_unescape(_: &Context, text: String) -> Result<String, LuaError> {
+    htmlescape::decode_html(&text)
+        .map_err(|e| RuntimeError(format!("Invalid HTML entity: {:?}", e)))
+}
+
+/// Works like math.random of Lua but every run of a factoid has its own generator.
+pub fn random<'l>(
+    rng: &mut StdRng,
+    (low, high): (Option<i64>, Option<i64>),
+) -> Result<LuaValue<'l>, LuaError> {
+    let (low, high) = match (low, high) {
+        (None, _) => return Ok(LuaValue::Number(rng.gen())),
+        (Some(high), None) => (1, high),
+        (Some(low), Some(high)) => (low, high),
+    };
+
+    if low > high {
+        return Err(RuntimeError(String::from(
+            "bad argument to 'random' (interval is empty)",
+        )));
+    }
+
+    Ok(LuaValue::Integer(
+        Uniform::new_inclusive(low, high).sample(rng),
+    ))
+}
+
+/// Works like os.date of Lua with an optional timezone like `Europe/Berlin`.
+/// Without one the local time is used unless the format starts with `!`.
+pub fn date<'l>(
+    lua: &Context<'l>,
+    (format, time, timezone): (Option<String>, Option<i64>, Option<String>),
+) -> Result<LuaValue<'l>, LuaError> {
+    let format = format.unwrap_or_else(|| String::from("%c"));
+    if format.len() > MAX_DATE_FORMAT_LEN {
+        return Err(RuntimeError(format!(
+            "The format can not be longer than {} bytes",
+            MAX_DATE_FORMAT_LEN
+        )));
+    }
+
+    let utc = match time {
+        Some(time) => Utc
+            .timestamp_opt(time, 0)
+            .single()
+            .ok_or_else(|| RuntimeError(String::from("The time is out of range")))?,
+        None => Utc::now(),
+    };
+
+    let (is_utc, format) = if format.starts_with('!') {
+        (true, &format[1..])
+    } else {
+        (false, &format[..])
+    };
+
+    match timezone {
+        Some(timezone) => {
+            let tz = timezone
+                .parse::<Tz>()
+                .map_err(|e| RuntimeError(format!("Unknown timezone: {}", e)))?;
+            format_date(lua, &utc.with_timezone(&tz), format)
+        }
+        None if is_utc => format_date(lua, &utc, format),
+        None => format_date(lua, &utc.with_timezone(&Local), format),
+    }
+}
+
+fn format_date<'l, T>(
+    lua: &Context<'l>,
+    date: &DateTime<T>,
+    format: &str,
+) -> Result<LuaValue<'l>, LuaError>
+where
+    T: TimeZone,
+    T::Offset: fmt::Display,
+{
+    if format.starts_with("*t") {
+        let table = lua.create_table()?;
+        table.set("year", date.year())?;
+        table.set("month", date.month())?;
+        table.set("day", date.day())?;
+        table.set("hour", date.hour())?;
+        table.set("min", date.minute())?;
+        table.set("sec", date.second())?;
+        table.set("wday", date.weekday().number_from_sunday())?;
+        table.set("yday", date.ordinal())?;
+        table.set("isdst", false)?;
+
+        return Ok(LuaValue::Table(table));
+    }
+
+    // Invalid specifiers make formatting fail
+    let mut text = String::new();
+    write!(text, "{}", date.format(format))
+        .map_err(|_| RuntimeError(String::from("Invalid date format")))?;
+
+    Ok(LuaValue::String(lua.create_string(&text)?))
+}
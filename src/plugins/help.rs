@@ -38,7 +38,7 @@ impl<C: FrippyClient> Plugin for Help<C> {
         client
             .send_privmsg(
                 &command.target,
-                "Available commands: help, tell, factoids, remind, quote, unicode\r\n\
+                "Available commands: help, tell, factoids, remind, quote, unicode, seen, log, urls\r\n\
                  For more detailed help call help on the specific command.\r\n\
                  Example: 'remind help'",
             )
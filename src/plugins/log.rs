@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use antidote::Mutex;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use humantime::parse_duration;
+use irc::client::prelude::*;
+use regex::{Regex, RegexBuilder};
+
+use crate::plugin::*;
+use crate::utils::Pager;
+use crate::FrippyClient;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::{Fail, ResultExt};
+use log::{debug, error, info};
+
+use frippy_derive::PluginName;
+
+/// Only the most recent matches of a search are returned.
+const MAX_RESULTS: usize = 100;
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TIME_FORMAT: &str = "%H:%M:%S";
+
+/// Writes channel transcripts into one file per channel and day.
+///
+/// The lines use the irssi format, for example:
+/// `12:34:56 <nick> message` or `12:34:56 -!- nick [user@host] has joined #channel`.
+#[derive(PluginName)]
+pub struct Log<C> {
+    dir: Option<PathBuf>,
+    retention: Option<Duration>,
+    last_cleanup: Mutex<Option<NaiveDate>>,
+    /// The lowercase nicks in each channel, used to log quits and nick changes.
+    members: Mutex<HashMap<String, HashSet<String>>>,
+    pager: Pager,
+    phantom: PhantomData<C>,
+}
+
+impl<C: FrippyClient> Log<C> {
+    /// Creates a `Log` which writes into `dir`.
+    /// If `dir` is `None` nothing is logged.
+    ///
+    /// Files older than `retention` are deleted once a day.
+    pub fn new(dir: Option<PathBuf>, retention: Option<Duration>) -> Self {
+        Log {
+            dir,
+            retention,
+            last_cleanup: Mutex::new(None),
+            members: Mutex::new(HashMap::new()),
+            pager: Pager::new(5, "log more"),
+            phantom: PhantomData,
+        }
+    }
+
+    fn channel_dir(dir: &Path, channel: &str) -> PathBuf {
+        // Channel names may contain slashes
+        dir.join(channel.to_lowercase().replace('/', "_"))
+    }
+
+    fn write(&self, channel: &str, line: &str) -> Result<(), LogError> {
+        let dir = match self.dir {
+            Some(ref dir) => Self::channel_dir(dir, channel),
+            None => return Ok(()),
+        };
+
+        let now = Local::now().naive_local();
+        self.cleanup(now.date());
+
+        fs::create_dir_all(&dir).context(ErrorKind::Write)?;
+        let path = dir.join(format!("{}.log", now.format(DATE_FORMAT)));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(ErrorKind::Write)?;
+
+        writeln!(file, "{} {}", now.format(TIME_FORMAT), line).context(ErrorKind::Write)?;
+
+        Ok(())
+    }
+
+    /// Deletes files older than the retention period if that did not happen today.
+    fn cleanup(&self, today: NaiveDate) {
+        let (dir, retention) = match (&self.dir, self.retention) {
+            (Some(dir), Some(retention)) => (dir, retention),
+            _ => return,
+        };
+
+        let mut last_cleanup = self.last_cleanup.lock();
+        if *last_cleanup == Some(today) {
+            return;
+        }
+        *last_cleanup = Some(today);
+
+        let oldest = today - retention;
+        for (path, date) in Self::log_files(dir, None) {
+            if date < oldest {
+                debug!("Deleting old log {}", path.display());
+                if let Err(e) = fs::remove_file(&path) {
+                    error!("Failed to delete {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Lists the log files of `channel` or of all channels with their dates.
+    fn log_files(dir: &Path, channel: Option<&str>) -> Vec<(PathBuf, NaiveDate)> {
+        let channel_dirs = match channel {
+            Some(channel) => vec![Self::channel_dir(dir, channel)],
+            None => match fs::read_dir(dir) {
+                Ok(entries) => entries.filter_map(Result::ok).map(|e| e.path()).collect(),
+                Err(_) => Vec::new(),
+            },
+        };
+
+        let mut files = channel_dirs
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.filter_map(Result::ok))
+            .filter_map(|entry| {
+                let path = entry.path();
+                let date = path.file_stem()?.to_str()?;
+                let date = NaiveDate::parse_from_str(date, DATE_FORMAT).ok()?;
+                Some((path, date))
+            })
+            .collect::<Vec<_>>();
+
+        files.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        files
+    }
+
+    fn log_message(&self, message: &Message) -> Result<(), LogError> {
+        let nick = match message.source_nickname() {
+            Some(nick) => nick,
+            None => return Ok(()),
+        };
+        let user_host = message
+            .prefix
+            .as_ref()
+            .and_then(|p| p.splitn(2, '!').nth(1))
+            .unwrap_or("");
+
+        match message.command {
+            Command::PRIVMSG(ref target, ref content) if is_channel(target) => {
+                let line = match action(content) {
+                    Some(action) => format!(" * {} {}", nick, action),
+                    None => format!("<{}> {}", nick, content),
+                };
+                self.write(target, &line)
+            }
+            Command::JOIN(ref channel, _, _) => {
+                self.add_member(channel, nick);
+                self.write(
+                    channel,
+                    &format!("-!- {} [{}] has joined {}", nick, user_host, channel),
+                )
+            }
+            Command::PART(ref channel, ref reason) => {
+                self.remove_member(channel, nick);
+                self.write(
+                    channel,
+                    &format!(
+                        "-!- {} [{}] has left {} [{}]",
+                        nick,
+                        user_host,
+                        channel,
+                        reason.as_ref().map_or("", String::as_str)
+                    ),
+                )
+            }
+            Command::KICK(ref channel, ref kicked, ref reason) => {
+                self.remove_member(channel, kicked);
+                self.write(
+                    channel,
+                    &format!(
+                        "-!- {} was kicked from {} by {} [{}]",
+                        kicked,
+                        channel,
+                        nick,
+                        reason.as_ref().map_or("", String::as_str)
+                    ),
+                )
+            }
+            Command::QUIT(ref reason) => {
+                let line = format!(
+                    "-!- {} [{}] has quit [{}]",
+                    nick,
+                    user_host,
+                    reason.as_ref().map_or("", String::as_str)
+                );
+                for channel in self.remove_from_all(nick) {
+                    self.write(&channel, &line)?;
+                }
+                Ok(())
+            }
+            Command::NICK(ref new_nick) => {
+                let line = format!("-!- {} is now known as {}", nick, new_nick);
+                for channel in self.remove_from_all(nick) {
+                    self.add_member(&channel, new_nick);
+                    self.write(&channel, &line)?;
+                }
+                Ok(())
+            }
+            Command::TOPIC(ref channel, Some(ref topic)) => self.write(
+                channel,
+                &format!(
+                    "-!- {} changed the topic of {} to: {}",
+                    nick, channel, topic
+                ),
+            ),
+            Command::Response(Response::RPL_NAMREPLY, ref args, Some(ref names)) => {
+                if let Some(channel) = args.last() {
+                    for name in names.split_whitespace() {
+                        self.add_member(channel, name.trim_start_matches(|c| "~&@%+".contains(c)));
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn add_member(&self, channel: &str, nick: &str) {
+        self.members
+            .lock()
+            .entry(channel.to_lowercase())
+            .or_insert_with(HashSet::new)
+            .insert(nick.to_lowercase());
+    }
+
+    fn remove_member(&self, channel: &str, nick: &str) {
+        if let Some(members) = self.members.lock().get_mut(&channel.to_lowercase()) {
+            members.remove(&nick.to_lowercase());
+        }
+    }
+
+    /// Removes `nick` from all channels and returns the channels it was in.
+    fn remove_from_all(&self, nick: &str) -> Vec<String> {
+        let nick = nick.to_lowercase();
+        let mut channels = self
+            .members
+            .lock()
+            .iter_mut()
+            .filter(|(_, members)| members.remove(&nick))
+            .map(|(channel, _)| channel.clone())
+            .collect::<Vec<_>>();
+        channels.sort();
+
+        channels
+    }
+
+    fn search(&self, client: &C, command: &PluginCommand) -> Result<Vec<String>, String> {
+        if !client.is_owner_mask(&command.prefix) {
+            return Err(String::from(
+                "Only owners listed with their hostmask can search the logs.",
+            ));
+        }
+
+        let dir = match self.dir {
+            Some(ref dir) => dir,
+            None => return Err(String::from("Logging is not enabled.")),
+        };
+
+        let re = match command.tokens.get(1) {
+            Some(re) => RegexBuilder::new(re)
+                .size_limit(1 << 20)
+                .build()
+                .map_err(|e| format!("Invalid regex: {}", e))?,
+            None => return Err(String::from(self.invalid_command())),
+        };
+
+        let mut channel = None;
+        let mut since = None;
+        for token in &command.tokens[2..] {
+            if is_channel(token) && channel.is_none() {
+                channel = Some(token.as_str());
+            } else if since.is_none() {
+                since = Some(parse_since(token)?);
+            } else {
+                return Err(String::from(self.invalid_command()));
+            }
+        }
+
+        let since = since.map(|since| (since.date(), since.format(TIME_FORMAT).to_string()));
+        // The files are sorted by date, so the last lines are the newest
+        let mut results = VecDeque::with_capacity(MAX_RESULTS);
+        for (path, date) in Self::log_files(dir, channel) {
+            if since.as_ref().map_or(false, |(day, _)| date < *day) {
+                continue;
+            }
+
+            let name = path
+                .parent()
+                .and_then(Path::file_name)
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let file = match fs::File::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Failed to read {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            for line in BufReader::new(file).lines().filter_map(Result::ok) {
+                // The time comes first so comparing strings is enough
+                let early = since.as_ref().map_or(false, |(day, time)| {
+                    date == *day && line.as_str() < time.as_str()
+                });
+
+                if !early && matches(&re, &line) {
+                    if results.len() == MAX_RESULTS {
+                        results.pop_front();
+                    }
+                    results.push_back(format!("{} {} {}", name, date, line));
+                }
+            }
+        }
+
+        let page = self.pager.start(&command.source, results);
+        if page.is_empty() {
+            Err(String::from("No matching lines found."))
+        } else {
+            Ok(page)
+        }
+    }
+
+    fn invalid_command(&self) -> &str {
+        "Incorrect Command. \
+         Send \"log help\" for help."
+    }
+
+    fn help(&self) -> &str {
+        "usage: log <subcommand>\r\n\
+         subcommands: search, more\r\n\
+         examples: log search frippy #frippy 2d\r\n\
+         log search (?i)hello 2019-03-01"
+    }
+}
+
+/// Returns the text of a CTCP ACTION.
+fn action(content: &str) -> Option<&str> {
+    if content.starts_with("\u{1}ACTION ") {
+        Some(content[8..].trim_end_matches('\u{1}'))
+    } else {
+        None
+    }
+}
+
+fn is_channel(target: &str) -> bool {
+    target.starts_with(|c| "#&+!".contains(c))
+}
+
+/// Searches only the message part of a line, ignoring the time.
+fn matches(re: &Regex, line: &str) -> bool {
+    match line.find(' ') {
+        Some(idx) => re.is_match(&line[idx + 1..]),
+        None => false,
+    }
+}
+
+/// Parses either a date like `2019-03-01` or a duration like `2d`.
+fn parse_since(since: &str) -> Result<NaiveDateTime, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(since, DATE_FORMAT) {
+        return Ok(date.and_hms(0, 0, 0));
+    }
+
+    let duration = parse_duration(since)
+        .ok()
+        .and_then(|d| Duration::from_std(d).ok())
+        .ok_or_else(|| format!("\"{}\" is neither a date nor a duration.", since))?;
+
+    Ok(Local::now().naive_local() - duration)
+}
+
+impl<C: FrippyClient> Plugin for Log<C> {
+    type Client = C;
+    fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
+        match self.log_message(message) {
+            Ok(_) => ExecutionStatus::Done,
+            Err(e) => ExecutionStatus::Err(e.context(FrippyErrorKind::Log).into()),
+        }
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Log should not use threading")
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        // The results may contain private channels, so they are only sent in a query
+        let lines = match command.tokens.get(0).map(String::as_str) {
+            Some("search") => match self.search(client, &command) {
+                Ok(lines) => {
+                    info!(
+                        "{} searched the logs for {:?}",
+                        command.source, command.tokens
+                    );
+                    lines
+                }
+                Err(e) => vec![e],
+            },
+            Some("more") => {
+                let page = self.pager.next(&command.source);
+                if page.is_empty() {
+                    vec![String::from("There is nothing left to show.")]
+                } else {
+                    page
+                }
+            }
+            Some("help") => vec![self.help().to_owned()],
+            _ => vec![self.invalid_command().to_owned()],
+        };
+
+        for line in lines {
+            client
+                .send_notice(&command.source, &line)
+                .context(FrippyErrorKind::Connection)?;
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from("Log does not implement evaluate"))
+    }
+}
+
+use std::fmt;
+impl<C> fmt::Debug for Log<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Log {{ dir: {:?} }}", self.dir)
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "LogError"]
+    pub enum ErrorKind {
+        /// Write error
+        #[fail(display = "Failed to write the log file")]
+        Write,
+    }
+}
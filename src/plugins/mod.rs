@@ -3,9 +3,11 @@ pub mod counter;
 pub mod factoid;
 pub mod help;
 pub mod keepnick;
+pub mod log;
 pub mod quote;
 pub mod remind;
 pub mod sed;
+pub mod seen;
 pub mod tell;
 pub mod unicode;
 pub mod url;
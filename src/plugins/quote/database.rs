@@ -1,24 +1,30 @@
 use std::collections::HashMap;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use std::sync::Arc;
 
 #[cfg(feature = "mysql")]
 use diesel::mysql::MysqlConnection;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+#[cfg(feature = "sql")]
 use diesel::prelude::*;
-#[cfg(feature = "mysql")]
-use failure::ResultExt;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+#[cfg(feature = "sql")]
 use r2d2::Pool;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use r2d2_diesel::ConnectionManager;
 
 use chrono::NaiveDateTime;
 
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+
 use super::error::*;
+use crate::storage::FileDatabase;
 
-#[cfg_attr(feature = "mysql", derive(Queryable))]
-#[derive(Clone, Debug)]
+#[cfg_attr(feature = "sql", derive(Queryable))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Quote {
     pub quotee: String,
     pub channel: String,
@@ -28,8 +34,8 @@ pub struct Quote {
     pub created: NaiveDateTime,
 }
 
-#[cfg_attr(feature = "mysql", derive(Insertable))]
-#[cfg_attr(feature = "mysql", table_name = "quotes")]
+#[cfg_attr(feature = "sql", derive(Insertable))]
+#[cfg_attr(feature = "sql", table_name = "quotes")]
 pub struct NewQuote<'a> {
     pub quotee: &'a str,
     pub channel: &'a str,
@@ -59,6 +65,7 @@ pub trait Database: Send + Sync {
         channel: &str,
         offset: i32,
     ) -> Result<Quote, QuoteError>;
+    fn list_quotes(&self) -> Result<Vec<Quote>, QuoteError>;
 }
 
 // HashMap
@@ -158,87 +165,33 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database
 
         Ok(quote)
     }
-}
 
-// Diesel automatically defines the quotes module as public.
-// We create a schema module to keep it private.
-#[cfg(feature = "mysql")]
-mod schema {
-    table! {
-        quotes (quotee, channel, idx) {
-            quotee -> Varchar,
-            channel -> Varchar,
-            idx -> Integer,
-            content -> Text,
-            author -> Varchar,
-            created -> Timestamp,
-        }
+    fn list_quotes(&self) -> Result<Vec<Quote>, QuoteError> {
+        Ok(self.values().cloned().collect())
     }
 }
 
-#[cfg(feature = "mysql")]
-use self::schema::quotes;
-
-#[cfg(feature = "mysql")]
-impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
+// File
+impl Database for FileDatabase<(String, String, i32), Quote> {
     fn insert_quote(&mut self, quote: &NewQuote) -> Result<(), QuoteError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        diesel::insert_into(quotes::table)
-            .values(quote)
-            .execute(conn)
-            .context(ErrorKind::MysqlError)?;
-
-        Ok(())
+        self.map_mut().insert_quote(quote)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
     }
 
     fn get_user_quote(&self, quotee: &str, channel: &str, idx: i32) -> Result<Quote, QuoteError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let quote = quotes::table
-            .find((quotee, channel, idx))
-            .first(conn)
-            .context(ErrorKind::MysqlError)?;
-
-        Ok(quote)
+        self.map().get_user_quote(quotee, channel, idx)
     }
 
     fn get_channel_quote(&self, channel: &str, idx: i32) -> Result<Quote, QuoteError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let quote = quotes::table
-            .filter(quotes::columns::channel.eq(channel))
-            .offset(idx as i64 - 1)
-            .first(conn)
-            .context(ErrorKind::MysqlError)?;
-
-        Ok(quote)
+        self.map().get_channel_quote(channel, idx)
     }
 
     fn count_user_quotes(&self, quotee: &str, channel: &str) -> Result<i32, QuoteError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let count: Result<i64, _> = quotes::table
-            .filter(quotes::columns::quotee.eq(quotee))
-            .filter(quotes::columns::channel.eq(channel))
-            .count()
-            .get_result(conn);
-
-        match count {
-            Ok(c) => Ok(c as i32),
-            Err(diesel::NotFound) => Ok(0),
-            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
-        }
+        self.map().count_user_quotes(quotee, channel)
     }
 
     fn count_channel_quotes(&self, channel: &str) -> Result<i32, QuoteError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let count: Result<i64, _> = quotes::table
-            .filter(quotes::columns::channel.eq(channel))
-            .count()
-            .get_result(conn);
-
-        match count {
-            Ok(c) => Ok(c as i32),
-            Err(diesel::NotFound) => Ok(0),
-            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
-        }
+        self.map().count_channel_quotes(channel)
     }
 
     fn search_user_quote(
@@ -248,16 +201,53 @@ impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
         channel: &str,
         offset: i32,
     ) -> Result<Quote, QuoteError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let quote = quotes::table
-            .filter(quotes::columns::channel.eq(channel))
-            .filter(quotes::columns::quotee.eq(quotee))
-            .filter(quotes::columns::content.like(&format!("%{}%", query)))
-            .offset(offset as i64)
-            .first(conn)
-            .context(ErrorKind::MysqlError)?;
+        self.map().search_user_quote(query, quotee, channel, offset)
+    }
 
-        Ok(quote)
+    fn search_channel_quote(
+        &self,
+        query: &str,
+        channel: &str,
+        offset: i32,
+    ) -> Result<Quote, QuoteError> {
+        self.map().search_channel_quote(query, channel, offset)
+    }
+
+    fn list_quotes(&self) -> Result<Vec<Quote>, QuoteError> {
+        self.map().list_quotes()
+    }
+}
+
+// Box
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn insert_quote(&mut self, quote: &NewQuote) -> Result<(), QuoteError> {
+        (**self).insert_quote(quote)
+    }
+
+    fn get_user_quote(&self, quotee: &str, channel: &str, idx: i32) -> Result<Quote, QuoteError> {
+        (**self).get_user_quote(quotee, channel, idx)
+    }
+
+    fn get_channel_quote(&self, channel: &str, idx: i32) -> Result<Quote, QuoteError> {
+        (**self).get_channel_quote(channel, idx)
+    }
+
+    fn count_user_quotes(&self, quotee: &str, channel: &str) -> Result<i32, QuoteError> {
+        (**self).count_user_quotes(quotee, channel)
+    }
+
+    fn count_channel_quotes(&self, channel: &str) -> Result<i32, QuoteError> {
+        (**self).count_channel_quotes(channel)
+    }
+
+    fn search_user_quote(
+        &self,
+        query: &str,
+        quotee: &str,
+        channel: &str,
+        offset: i32,
+    ) -> Result<Quote, QuoteError> {
+        (**self).search_user_quote(query, quotee, channel, offset)
     }
 
     fn search_channel_quote(
@@ -266,14 +256,155 @@ impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
         channel: &str,
         offset: i32,
     ) -> Result<Quote, QuoteError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let quote = quotes::table
-            .filter(quotes::columns::channel.eq(channel))
-            .filter(quotes::columns::content.like(&format!("%{}%", query)))
-            .offset(offset as i64)
-            .first(conn)
-            .context(ErrorKind::MysqlError)?;
+        (**self).search_channel_quote(query, channel, offset)
+    }
 
-        Ok(quote)
+    fn list_quotes(&self) -> Result<Vec<Quote>, QuoteError> {
+        (**self).list_quotes()
     }
 }
+
+// Diesel automatically defines the quotes module as public.
+// We create a schema module to keep it private.
+#[cfg(feature = "sql")]
+mod schema {
+    table! {
+        quotes (quotee, channel, idx) {
+            quotee -> Varchar,
+            channel -> Varchar,
+            idx -> Integer,
+            content -> Text,
+            author -> Varchar,
+            created -> Timestamp,
+        }
+    }
+}
+
+#[cfg(feature = "sql")]
+use self::schema::quotes;
+
+// The queries are the same for every backend
+#[cfg(feature = "sql")]
+macro_rules! impl_database {
+    ($connection:ty, $error:ident) => {
+        impl Database for Arc<Pool<ConnectionManager<$connection>>> {
+            fn insert_quote(&mut self, quote: &NewQuote) -> Result<(), QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                diesel::insert_into(quotes::table)
+                    .values(quote)
+                    .execute(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(())
+            }
+
+            fn get_user_quote(
+                &self,
+                quotee: &str,
+                channel: &str,
+                idx: i32,
+            ) -> Result<Quote, QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let quote = quotes::table
+                    .find((quotee, channel, idx))
+                    .first(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(quote)
+            }
+
+            fn get_channel_quote(&self, channel: &str, idx: i32) -> Result<Quote, QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let quote = quotes::table
+                    .filter(quotes::columns::channel.eq(channel))
+                    .offset(idx as i64 - 1)
+                    .first(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(quote)
+            }
+
+            fn count_user_quotes(&self, quotee: &str, channel: &str) -> Result<i32, QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let count: Result<i64, _> = quotes::table
+                    .filter(quotes::columns::quotee.eq(quotee))
+                    .filter(quotes::columns::channel.eq(channel))
+                    .count()
+                    .get_result(conn);
+
+                match count {
+                    Ok(c) => Ok(c as i32),
+                    Err(diesel::NotFound) => Ok(0),
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
+
+            fn count_channel_quotes(&self, channel: &str) -> Result<i32, QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let count: Result<i64, _> = quotes::table
+                    .filter(quotes::columns::channel.eq(channel))
+                    .count()
+                    .get_result(conn);
+
+                match count {
+                    Ok(c) => Ok(c as i32),
+                    Err(diesel::NotFound) => Ok(0),
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
+
+            fn search_user_quote(
+                &self,
+                query: &str,
+                quotee: &str,
+                channel: &str,
+                offset: i32,
+            ) -> Result<Quote, QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let quote = quotes::table
+                    .filter(quotes::columns::channel.eq(channel))
+                    .filter(quotes::columns::quotee.eq(quotee))
+                    .filter(quotes::columns::content.like(&format!("%{}%", query)))
+                    .offset(offset as i64)
+                    .first(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(quote)
+            }
+
+            fn search_channel_quote(
+                &self,
+                query: &str,
+                channel: &str,
+                offset: i32,
+            ) -> Result<Quote, QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let quote = quotes::table
+                    .filter(quotes::columns::channel.eq(channel))
+                    .filter(quotes::columns::content.like(&format!("%{}%", query)))
+                    .offset(offset as i64)
+                    .first(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(quote)
+            }
+
+            fn list_quotes(&self) -> Result<Vec<Quote>, QuoteError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(quotes::table
+                    .load::<Quote>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "mysql")]
+impl_database!(MysqlConnection, MysqlError);
+
+#[cfg(feature = "sqlite")]
+impl_database!(SqliteConnection, SqliteError);
+
+#[cfg(feature = "postgres")]
+impl_database!(PgConnection, PostgresError);
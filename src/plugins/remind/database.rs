@@ -2,30 +2,39 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt;
 
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use std::sync::Arc;
 
 #[cfg(feature = "mysql")]
 use diesel::mysql::MysqlConnection;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+#[cfg(feature = "sql")]
 use diesel::prelude::*;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+#[cfg(feature = "sql")]
 use r2d2::Pool;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use r2d2_diesel::ConnectionManager;
 
-#[cfg(feature = "mysql")]
-use failure::ResultExt;
-
 use chrono::NaiveDateTime;
 
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+
 use super::error::*;
+use crate::storage::FileDatabase;
 
 #[cfg(feature = "mysql")]
-static LAST_ID_SQL: &'static str = "SELECT LAST_INSERT_ID()";
-
-#[cfg_attr(feature = "mysql", derive(Queryable))]
-#[derive(Clone, Debug)]
+static MYSQL_LAST_ID_SQL: &'static str = "SELECT LAST_INSERT_ID()";
+#[cfg(feature = "sqlite")]
+static SQLITE_LAST_ID_SQL: &'static str = "SELECT last_insert_rowid()";
+#[cfg(feature = "postgres")]
+static POSTGRES_LAST_ID_SQL: &'static str = "SELECT lastval()";
+
+#[cfg_attr(feature = "sql", derive(Queryable))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Event {
     pub id: i64,
     pub receiver: String,
@@ -45,8 +54,8 @@ impl fmt::Display for Event {
     }
 }
 
-#[cfg_attr(feature = "mysql", derive(Insertable))]
-#[cfg_attr(feature = "mysql", table_name = "events")]
+#[cfg_attr(feature = "sql", derive(Insertable))]
+#[cfg_attr(feature = "sql", table_name = "events")]
 #[derive(Debug)]
 pub struct NewEvent<'a> {
     pub receiver: &'a str,
@@ -63,6 +72,7 @@ pub trait Database: Send + Sync {
     fn get_user_events(&self, user: &str) -> Result<Vec<Event>, RemindError>;
     fn get_event(&self, id: i64) -> Result<Event, RemindError>;
     fn delete_event(&mut self, id: i64) -> Result<(), RemindError>;
+    fn list_events(&self) -> Result<Vec<Event>, RemindError>;
 }
 
 // HashMap
@@ -141,9 +151,80 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<i64, Event,
             None => Err(ErrorKind::NotFound)?,
         }
     }
+
+    fn list_events(&self) -> Result<Vec<Event>, RemindError> {
+        Ok(self.values().cloned().collect())
+    }
 }
 
-#[cfg(feature = "mysql")]
+// File
+impl Database for FileDatabase<i64, Event> {
+    fn insert_event(&mut self, event: &NewEvent) -> Result<i64, RemindError> {
+        let id = self.map_mut().insert_event(event)?;
+        self.flush().context(ErrorKind::FileError)?;
+
+        Ok(id)
+    }
+
+    fn update_event_time(&mut self, id: i64, time: &NaiveDateTime) -> Result<(), RemindError> {
+        self.map_mut().update_event_time(id, time)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn get_events_before(&self, time: &NaiveDateTime) -> Result<Vec<Event>, RemindError> {
+        self.map().get_events_before(time)
+    }
+
+    fn get_user_events(&self, user: &str) -> Result<Vec<Event>, RemindError> {
+        self.map().get_user_events(user)
+    }
+
+    fn get_event(&self, id: i64) -> Result<Event, RemindError> {
+        self.map().get_event(id)
+    }
+
+    fn delete_event(&mut self, id: i64) -> Result<(), RemindError> {
+        self.map_mut().delete_event(id)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn list_events(&self) -> Result<Vec<Event>, RemindError> {
+        self.map().list_events()
+    }
+}
+
+// Box
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn insert_event(&mut self, event: &NewEvent) -> Result<i64, RemindError> {
+        (**self).insert_event(event)
+    }
+
+    fn update_event_time(&mut self, id: i64, time: &NaiveDateTime) -> Result<(), RemindError> {
+        (**self).update_event_time(id, time)
+    }
+
+    fn get_events_before(&self, time: &NaiveDateTime) -> Result<Vec<Event>, RemindError> {
+        (**self).get_events_before(time)
+    }
+
+    fn get_user_events(&self, user: &str) -> Result<Vec<Event>, RemindError> {
+        (**self).get_user_events(user)
+    }
+
+    fn get_event(&self, id: i64) -> Result<Event, RemindError> {
+        (**self).get_event(id)
+    }
+
+    fn delete_event(&mut self, id: i64) -> Result<(), RemindError> {
+        (**self).delete_event(id)
+    }
+
+    fn list_events(&self) -> Result<Vec<Event>, RemindError> {
+        (**self).list_events()
+    }
+}
+
+#[cfg(feature = "sql")]
 mod schema {
     table! {
         events (id) {
@@ -157,78 +238,104 @@ mod schema {
     }
 }
 
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use self::schema::events;
 
-#[cfg(feature = "mysql")]
-impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
-    fn insert_event(&mut self, event: &NewEvent) -> Result<i64, RemindError> {
-        use diesel::{dsl::sql, types::Bigint};
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-
-        diesel::insert_into(events::table)
-            .values(event)
-            .execute(conn)
-            .context(ErrorKind::MysqlError)?;
+// The queries are the same for every backend
+#[cfg(feature = "sql")]
+macro_rules! impl_database {
+    ($connection:ty, $error:ident, $last_id_sql:expr) => {
+        impl Database for Arc<Pool<ConnectionManager<$connection>>> {
+            fn insert_event(&mut self, event: &NewEvent) -> Result<i64, RemindError> {
+                use diesel::{dsl::sql, types::Bigint};
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                diesel::insert_into(events::table)
+                    .values(event)
+                    .execute(conn)
+                    .context(ErrorKind::$error)?;
+
+                let id = sql::<Bigint>($last_id_sql)
+                    .get_result(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(id)
+            }
 
-        let id = sql::<Bigint>(LAST_ID_SQL)
-            .get_result(conn)
-            .context(ErrorKind::MysqlError)?;
+            fn update_event_time(
+                &mut self,
+                id: i64,
+                time: &NaiveDateTime,
+            ) -> Result<(), RemindError> {
+                use self::events::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                match diesel::update(events::table.filter(columns::id.eq(id)))
+                    .set(columns::time.eq(time))
+                    .execute(conn)
+                {
+                    Ok(0) => Err(ErrorKind::NotFound)?,
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
 
-        Ok(id)
-    }
+            fn get_events_before(&self, time: &NaiveDateTime) -> Result<Vec<Event>, RemindError> {
+                use self::events::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
 
-    fn update_event_time(&mut self, id: i64, time: &NaiveDateTime) -> Result<(), RemindError> {
-        use self::events::columns;
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-
-        match diesel::update(events::table.filter(columns::id.eq(id)))
-            .set(columns::time.eq(time))
-            .execute(conn)
-        {
-            Ok(0) => Err(ErrorKind::NotFound)?,
-            Ok(_) => Ok(()),
-            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
-        }
-    }
+                Ok(events::table
+                    .filter(columns::time.lt(time))
+                    .load::<Event>(conn)
+                    .context(ErrorKind::$error)?)
+            }
 
-    fn get_events_before(&self, time: &NaiveDateTime) -> Result<Vec<Event>, RemindError> {
-        use self::events::columns;
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+            fn get_user_events(&self, user: &str) -> Result<Vec<Event>, RemindError> {
+                use self::events::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
 
-        Ok(events::table
-            .filter(columns::time.lt(time))
-            .load::<Event>(conn)
-            .context(ErrorKind::MysqlError)?)
-    }
+                Ok(events::table
+                    .filter(columns::receiver.eq(user))
+                    .load::<Event>(conn)
+                    .context(ErrorKind::$error)?)
+            }
 
-    fn get_user_events(&self, user: &str) -> Result<Vec<Event>, RemindError> {
-        use self::events::columns;
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+            fn get_event(&self, id: i64) -> Result<Event, RemindError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
 
-        Ok(events::table
-            .filter(columns::receiver.eq(user))
-            .load::<Event>(conn)
-            .context(ErrorKind::MysqlError)?)
-    }
+                Ok(events::table
+                    .find(id)
+                    .first(conn)
+                    .context(ErrorKind::$error)?)
+            }
 
-    fn get_event(&self, id: i64) -> Result<Event, RemindError> {
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+            fn delete_event(&mut self, id: i64) -> Result<(), RemindError> {
+                use self::events::columns;
 
-        Ok(events::table
-            .find(id)
-            .first(conn)
-            .context(ErrorKind::MysqlError)?)
-    }
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                match diesel::delete(events::table.filter(columns::id.eq(id))).execute(conn) {
+                    Ok(0) => Err(ErrorKind::NotFound)?,
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
 
-    fn delete_event(&mut self, id: i64) -> Result<(), RemindError> {
-        use self::events::columns;
+            fn list_events(&self) -> Result<Vec<Event>, RemindError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
 
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        match diesel::delete(events::table.filter(columns::id.eq(id))).execute(conn) {
-            Ok(0) => Err(ErrorKind::NotFound)?,
-            Ok(_) => Ok(()),
-            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+                Ok(events::table
+                    .load::<Event>(conn)
+                    .context(ErrorKind::$error)?)
+            }
         }
-    }
+    };
 }
+
+#[cfg(feature = "mysql")]
+impl_database!(MysqlConnection, MysqlError, MYSQL_LAST_ID_SQL);
+
+#[cfg(feature = "sqlite")]
+impl_database!(SqliteConnection, SqliteError, SQLITE_LAST_ID_SQL);
+
+#[cfg(feature = "postgres")]
+impl_database!(PgConnection, PostgresError, POSTGRES_LAST_ID_SQL);
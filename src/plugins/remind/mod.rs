@@ -332,13 +332,27 @@ pub mod error {
         #[fail(display = "No events found")]
         NotFound,
 
+        /// File error
+        #[fail(display = "Failed to save to the data file")]
+        FileError,
+
         /// MySQL error
         #[cfg(feature = "mysql")]
         #[fail(display = "Failed to execute MySQL Query")]
         MysqlError,
 
+        /// SQLite error
+        #[cfg(feature = "sqlite")]
+        #[fail(display = "Failed to execute SQLite Query")]
+        SqliteError,
+
+        /// PostgreSQL error
+        #[cfg(feature = "postgres")]
+        #[fail(display = "Failed to execute PostgreSQL Query")]
+        PostgresError,
+
         /// No connection error
-        #[cfg(feature = "mysql")]
+        #[cfg(feature = "sql")]
         #[fail(display = "No connection to the database")]
         NoConnection,
     }
@@ -0,0 +1,362 @@
+//! Parsing and evaluation of sed expressions like `s/a/b/g;y/abc/xyz/`.
+use std::iter::Peekable;
+use std::str::Chars;
+
+use regex::{Error as RegexError, Regex, RegexBuilder};
+
+use super::error::*;
+use failure::ResultExt;
+
+/// Limits for the compiled regex, the defaults allow several megabytes.
+const REGEX_SIZE_LIMIT: usize = 1 << 16;
+const DFA_SIZE_LIMIT: usize = 1 << 20;
+const NEST_LIMIT: u32 = 32;
+/// Results longer than this would not fit into a single IRC message anyway.
+const MAX_OUTPUT_LEN: usize = 400;
+/// Other characters like quotes start normal messages too often.
+const DELIMITERS: &str = "/|#:!@_%,";
+
+/// A single sed expression.
+#[derive(Debug)]
+pub enum Expression {
+    /// `s/regex/replacement/flags`
+    Substitute {
+        regex: Regex,
+        replacement: String,
+        occurrence: usize,
+        global: bool,
+    },
+    /// `y/abc/xyz/`
+    Transliterate { from: Vec<char>, to: Vec<char> },
+}
+
+impl Expression {
+    /// Applies the expression and returns `None` if nothing was changed.
+    pub fn apply(&self, text: &str) -> Result<Option<String>, SedError> {
+        match *self {
+            Expression::Substitute {
+                ref regex,
+                ref replacement,
+                occurrence,
+                global,
+            } => substitute(regex, replacement, occurrence, global, text),
+            Expression::Transliterate { ref from, ref to } => {
+                let result = text
+                    .chars()
+                    .map(|c| from.iter().position(|&f| f == c).map_or(c, |i| to[i]))
+                    .collect::<String>();
+
+                if result.len() > MAX_OUTPUT_LEN {
+                    Err(ErrorKind::OutputTooLong)?
+                } else if result == text {
+                    Ok(None)
+                } else {
+                    Ok(Some(result))
+                }
+            }
+        }
+    }
+}
+
+/// Applies all expressions in order and returns `None` if nothing was changed.
+pub fn apply_all(expressions: &[Expression], text: &str) -> Result<Option<String>, SedError> {
+    let mut changed = false;
+    let mut result = text.to_owned();
+
+    for expression in expressions {
+        if let Some(new) = expression.apply(&result)? {
+            result = new;
+            changed = true;
+        }
+    }
+
+    if changed {
+        Ok(Some(result))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Replaces the `occurrence`th match, or every match starting from it if `global` is set.
+fn substitute(
+    regex: &Regex,
+    replacement: &str,
+    occurrence: usize,
+    global: bool,
+    text: &str,
+) -> Result<Option<String>, SedError> {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut changed = false;
+
+    for (i, captures) in regex.captures_iter(text).enumerate().skip(occurrence - 1) {
+        if i >= occurrence && !global {
+            break;
+        }
+
+        let whole = captures.get(0).expect("group 0 always exists");
+        result.push_str(&text[last..whole.start()]);
+        captures.expand(replacement, &mut result);
+        last = whole.end();
+        changed = true;
+
+        // Stop early instead of building a huge string
+        if result.len() > MAX_OUTPUT_LEN {
+            Err(ErrorKind::OutputTooLong)?;
+        }
+    }
+
+    if !changed {
+        return Ok(None);
+    }
+
+    result.push_str(&text[last..]);
+    if result.len() > MAX_OUTPUT_LEN {
+        Err(ErrorKind::OutputTooLong)?;
+    }
+
+    Ok(Some(result))
+}
+
+/// A character of an expression part which remembers if it was escaped.
+#[derive(Clone, Copy, Debug)]
+enum Token {
+    Plain(char),
+    Escaped(char),
+}
+
+/// The expression parts before they are compiled.
+struct RawExpression {
+    command: char,
+    delimiter: char,
+    first: Vec<Token>,
+    second: Vec<Token>,
+    flags: String,
+}
+
+/// Parses a message like `nick: s/a/b/;y/c/d/`.
+///
+/// Returns `None` if the message is not a sed command at all,
+/// otherwise the targeted nick and the compiled expressions.
+pub fn parse(input: &str) -> Option<Result<(Option<&str>, Vec<Expression>), SedError>> {
+    let input = input.trim_end();
+
+    // The nick prefix is optional, so "s:a:b:" is not a nick
+    let (nick, raw) = match nick_prefix(input).and_then(|(nick, rest)| Some((nick, split(rest)?))) {
+        Some((nick, raw)) => (Some(nick), raw),
+        None => (None, split(input)?),
+    };
+
+    Some(
+        raw.iter()
+            .map(compile)
+            .collect::<Result<_, _>>()
+            .map(|e| (nick, e)),
+    )
+}
+
+fn nick_prefix(input: &str) -> Option<(&str, &str)> {
+    let idx = input.find(|c| c == ':' || c == ',')?;
+    let nick = &input[..idx];
+
+    if nick.is_empty() || nick.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some((nick, input[idx + 1..].trim_start()))
+}
+
+/// Splits the expressions without interpreting them.
+fn split(input: &str) -> Option<Vec<RawExpression>> {
+    let mut chars = input.chars().peekable();
+    let mut expressions = Vec::new();
+
+    loop {
+        let command = chars.next().filter(|&c| c == 's' || c == 'y')?;
+        let delimiter = chars.next().filter(|&c| DELIMITERS.contains(c))?;
+        let first = read_part(&mut chars, delimiter)?;
+        let second = read_part(&mut chars, delimiter)?;
+
+        let mut flags = String::new();
+        while let Some(&c) = chars.peek() {
+            if !c.is_alphanumeric() {
+                break;
+            }
+            flags.push(c);
+            chars.next();
+        }
+
+        expressions.push(RawExpression {
+            command,
+            delimiter,
+            first,
+            second,
+            flags,
+        });
+
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => return Some(expressions),
+            Some(';') => skip_whitespace(&mut chars),
+            Some(_) => return None,
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// Reads until the unescaped `delimiter`.
+fn read_part(chars: &mut Peekable<Chars>, delimiter: char) -> Option<Vec<Token>> {
+    let mut part = Vec::new();
+
+    loop {
+        match chars.next()? {
+            '\\' => part.push(Token::Escaped(chars.next()?)),
+            c if c == delimiter => return Some(part),
+            c => part.push(Token::Plain(c)),
+        }
+    }
+}
+
+fn compile(raw: &RawExpression) -> Result<Expression, SedError> {
+    match raw.command {
+        's' => compile_substitute(raw),
+        _ => compile_transliterate(raw),
+    }
+}
+
+fn compile_substitute(raw: &RawExpression) -> Result<Expression, SedError> {
+    let mut occurrence = 1;
+    let mut global = false;
+    let mut builder = RegexBuilder::new(&regex_part(&raw.first, raw.delimiter));
+    builder
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(DFA_SIZE_LIMIT)
+        .nest_limit(NEST_LIMIT);
+
+    let digits = raw
+        .flags
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>();
+    if !digits.is_empty() {
+        occurrence = digits.parse().context(ErrorKind::InvalidFlag)?;
+        if occurrence == 0 {
+            Err(ErrorKind::InvalidFlag)?;
+        }
+    }
+
+    for flag in raw.flags.chars().filter(|c| !c.is_ascii_digit()) {
+        match flag {
+            'g' => global = true,
+            'i' => {
+                builder.case_insensitive(true);
+            }
+            'x' => {
+                builder.ignore_whitespace(true);
+            }
+            'U' => {
+                builder.swap_greed(true);
+            }
+            'u' => {
+                builder.unicode(false);
+            }
+            _ => Err(ErrorKind::InvalidFlag)?,
+        }
+    }
+
+    let regex = match builder.build() {
+        Ok(regex) => regex,
+        Err(RegexError::CompiledTooBig(_)) => Err(ErrorKind::RegexTooBig)?,
+        Err(e) => Err(e).context(ErrorKind::InvalidRegex)?,
+    };
+
+    Ok(Expression::Substitute {
+        regex,
+        replacement: replacement_part(&raw.second, raw.delimiter),
+        occurrence,
+        global,
+    })
+}
+
+fn compile_transliterate(raw: &RawExpression) -> Result<Expression, SedError> {
+    if !raw.flags.is_empty() {
+        Err(ErrorKind::InvalidFlag)?;
+    }
+
+    let from = unescape(&raw.first, raw.delimiter);
+    let to = unescape(&raw.second, raw.delimiter);
+    if from.len() != to.len() {
+        Err(ErrorKind::TransliterationLength)?;
+    }
+
+    Ok(Expression::Transliterate { from, to })
+}
+
+/// Turns escaped delimiters into literals, other escapes are kept for the regex.
+fn regex_part(part: &[Token], delimiter: char) -> String {
+    let mut output = String::with_capacity(part.len());
+
+    for token in part {
+        match *token {
+            Token::Escaped(c) if c == delimiter => output.push_str(&regex::escape(&c.to_string())),
+            Token::Escaped(c) => {
+                output.push('\\');
+                output.push(c);
+            }
+            Token::Plain(c) => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Translates sed replacement syntax into the syntax of the regex crate.
+///
+/// `&` is the whole match and `\1` a group, `$1` keeps working as well.
+fn replacement_part(part: &[Token], delimiter: char) -> String {
+    let mut output = String::with_capacity(part.len());
+
+    for token in part {
+        match *token {
+            Token::Plain('&') => output.push_str("${0}"),
+            Token::Plain(c) => output.push(c),
+            Token::Escaped('&') => output.push('&'),
+            Token::Escaped('$') => output.push_str("$$"),
+            Token::Escaped('\\') => output.push('\\'),
+            Token::Escaped(c) if c.is_ascii_digit() => {
+                output.push_str("${");
+                output.push(c);
+                output.push('}');
+            }
+            Token::Escaped(c) if c == delimiter => output.push(c),
+            Token::Escaped(c) => {
+                output.push('\\');
+                output.push(c);
+            }
+        }
+    }
+
+    output
+}
+
+fn unescape(part: &[Token], delimiter: char) -> Vec<char> {
+    let mut output = Vec::with_capacity(part.len());
+
+    for token in part {
+        match *token {
+            Token::Escaped(c) if c == delimiter || c == '\\' => output.push(c),
+            Token::Escaped(c) => {
+                output.push('\\');
+                output.push(c);
+            }
+            Token::Plain(c) => output.push(c),
+        }
+    }
+
+    output
+}
@@ -3,12 +3,11 @@ use std::marker::PhantomData;
 
 use antidote::RwLock;
 use circular_queue::CircularQueue;
-use lazy_static::lazy_static;
-use regex::{Captures, Regex, RegexBuilder};
 
 use irc::client::prelude::*;
 
 use crate::plugin::*;
+use crate::utils::strip_control_codes;
 use crate::FrippyClient;
 
 use self::error::*;
@@ -20,15 +19,46 @@ use log::debug;
 
 use frippy_derive::PluginName;
 
-lazy_static! {
-    static ref RE: Regex =
-        Regex::new(r"^s/((?:\\/|[^/])+)/((?:\\/|[^/])*)/(?:(\w+))?\s*$").unwrap();
+mod expression;
+use self::expression::Expression;
+
+/// A message in the history of a channel.
+#[derive(Clone, Debug)]
+struct Line {
+    author: String,
+    message: String,
+    /// Sent with /me
+    action: bool,
+}
+
+impl Line {
+    fn new(author: &str, content: &str) -> Self {
+        let (message, action) = match parse_action(content) {
+            Some(action) => (action, true),
+            None => (content, false),
+        };
+
+        Line {
+            author: author.to_owned(),
+            message: strip_control_codes(message),
+            action,
+        }
+    }
+}
+
+/// Returns the text of a CTCP ACTION.
+fn parse_action(content: &str) -> Option<&str> {
+    if content.starts_with("\x01ACTION ") {
+        Some(content[8..].trim_end_matches('\x01'))
+    } else {
+        None
+    }
 }
 
 #[derive(PluginName, Debug)]
 pub struct Sed<C> {
     per_channel: usize,
-    channel_messages: RwLock<HashMap<String, CircularQueue<String>>>,
+    channel_messages: RwLock<HashMap<String, CircularQueue<Line>>>,
     phantom: PhantomData<C>,
 }
 
@@ -41,75 +71,55 @@ impl<C: FrippyClient> Sed<C> {
         }
     }
 
-    fn add_message(&self, channel: String, message: String) {
+    fn add_message(&self, channel: &str, line: Line) {
         let mut channel_messages = self.channel_messages.write();
         let messages = channel_messages
-            .entry(channel)
+            .entry(channel.to_owned())
             .or_insert_with(|| CircularQueue::with_capacity(self.per_channel));
-        messages.push(message);
+        messages.push(line);
     }
 
-    fn format_escaped(&self, input: &str) -> String {
-        let mut output = String::with_capacity(input.len());
-        let mut escape = false;
-
-        for c in input.chars() {
-            if escape && !r"/\".contains(c) {
-                output.push('\\');
-            } else if !escape && c == '\\' {
-                escape = true;
-                continue;
-            }
-            escape = false;
-
-            output.push(c);
-        }
-
-        output
+    /// Applies the expressions to the latest message of `author` which they change.
+    /// The corrected line is added to the history so it can be corrected again.
+    fn run_expressions(
+        &self,
+        channel: &str,
+        author: &str,
+        expressions: &[Expression],
+    ) -> Result<String, SedError> {
+        let line = self.find_and_apply(channel, author, expressions)?;
+        let response = if line.action {
+            format!("* {} {}", line.author, line.message)
+        } else {
+            format!("<{}> meant: {}", line.author, line.message)
+        };
+
+        self.add_message(channel, line);
+
+        Ok(response)
     }
 
-    fn run_regex(&self, channel: &str, captures: &Captures) -> Result<String, SedError> {
-        let mut global_match = false;
-        let mut case_insens = false;
-        let mut ign_whitespace = false;
-        let mut swap_greed = false;
-        let mut enable_unicode = true;
-
-        debug!("{:?}", captures);
-
-        let first = self.format_escaped(captures.get(1).unwrap().as_str());
-        let second = self.format_escaped(captures.get(2).unwrap().as_str());
-
-        if let Some(flags) = captures.get(3) {
-            let flags = flags.as_str();
-
-            global_match = flags.contains('g');
-            case_insens = flags.contains('i');
-            ign_whitespace = flags.contains('x');
-            swap_greed = flags.contains('U');
-            enable_unicode = !flags.contains('u');
-        }
-
-        let user_re = RegexBuilder::new(&first)
-            .case_insensitive(case_insens)
-            .ignore_whitespace(ign_whitespace)
-            .unicode(enable_unicode)
-            .swap_greed(swap_greed)
-            .build()
-            .context(ErrorKind::InvalidRegex)?;
+    fn find_and_apply(
+        &self,
+        channel: &str,
+        author: &str,
+        expressions: &[Expression],
+    ) -> Result<Line, SedError> {
+        debug!("{:?}", expressions);
 
         let channel_messages = self.channel_messages.read();
         let messages = channel_messages.get(channel).ok_or(ErrorKind::NoMessages)?;
 
-        for message in messages.iter() {
-            if user_re.is_match(message) {
-                let response = if global_match {
-                    user_re.replace_all(message, &second[..])
-                } else {
-                    user_re.replace(message, &second[..])
-                };
-
-                return Ok(response.to_string());
+        for line in messages
+            .iter()
+            .filter(|line| line.author.eq_ignore_ascii_case(author))
+        {
+            if let Some(result) = expression::apply_all(expressions, &line.message)? {
+                return Ok(Line {
+                    author: line.author.clone(),
+                    message: strip_control_codes(&result),
+                    action: line.action,
+                });
             }
         }
 
@@ -128,8 +138,11 @@ impl<C: FrippyClient> Plugin for Sed<C> {
                     return ExecutionStatus::Done;
                 }
 
-                if let Some(captures) = RE.captures(content) {
-                    let result = match self.run_regex(channel, &captures) {
+                if let Some(parsed) = expression::parse(content) {
+                    // Without a nick the sender corrects themselves
+                    let result = match parsed.and_then(|(nick, expressions)| {
+                        self.run_expressions(channel, nick.unwrap_or(user), &expressions)
+                    }) {
                         Ok(msg) => client.send_privmsg(channel, &msg),
                         Err(e) => match e.kind() {
                             ErrorKind::InvalidRegex => {
@@ -147,7 +160,7 @@ impl<C: FrippyClient> Plugin for Sed<C> {
                         Ok(_) => ExecutionStatus::Done,
                     }
                 } else {
-                    self.add_message(channel.to_string(), content.to_string());
+                    self.add_message(channel, Line::new(user, content));
 
                     ExecutionStatus::Done
                 }
@@ -189,12 +202,28 @@ pub mod error {
         #[fail(display = "Invalid regex")]
         InvalidRegex,
 
+        /// Invalid flag error
+        #[fail(display = "Unknown or invalid flag")]
+        InvalidFlag,
+
+        /// Transliteration length error
+        #[fail(display = "Both parts of y/// need the same number of characters")]
+        TransliterationLength,
+
+        /// Regex too big error
+        #[fail(display = "The regex is too large")]
+        RegexTooBig,
+
+        /// Output too long error
+        #[fail(display = "The result would be too long")]
+        OutputTooLong,
+
         /// No messages found error
         #[fail(display = "No messages were found for this channel")]
         NoMessages,
 
         /// No match found error
-        #[fail(display = "No recent messages match this regex")]
+        #[fail(display = "No recent messages of this user match this regex")]
         NoMatch,
     }
 }
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+#[cfg(feature = "sql")]
+use std::sync::Arc;
+
+#[cfg(feature = "mysql")]
+use diesel::mysql::MysqlConnection;
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+#[cfg(feature = "sql")]
+use r2d2::Pool;
+#[cfg(feature = "sql")]
+use r2d2_diesel::ConnectionManager;
+
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+
+use super::error::*;
+use crate::storage::FileDatabase;
+
+/// Seen is updated on every message so the file is only written
+/// after this many updates or once the last write is older than `MAX_UNSAVED_AGE`.
+/// The plugin calls `save()` regularly so quiet periods do not keep updates unsaved.
+const MAX_UNSAVED: usize = 100;
+const MAX_UNSAVED_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// The last activity of a nick on a network.
+///
+/// `nick` is lowercase while `name` keeps the case which was used.
+/// Depending on `activity` the `content` is the message,
+/// the part or quit reason or the other nick of a nick change.
+#[cfg_attr(feature = "sql", derive(Queryable))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Seen {
+    pub network: String,
+    pub nick: String,
+    pub name: String,
+    pub activity: String,
+    pub channel: Option<String>,
+    pub content: Option<String>,
+    pub time: NaiveDateTime,
+}
+
+#[cfg_attr(feature = "sql", derive(Insertable))]
+#[cfg_attr(feature = "sql", table_name = "seen")]
+pub struct NewSeen<'a> {
+    pub network: &'a str,
+    pub nick: &'a str,
+    pub name: &'a str,
+    pub activity: &'a str,
+    pub channel: Option<&'a str>,
+    pub content: Option<&'a str>,
+    pub time: NaiveDateTime,
+}
+
+pub trait Database: Send + Sync {
+    /// Replaces the previous activity of the nick.
+    fn insert_seen(&mut self, seen: &NewSeen) -> Result<(), SeenError>;
+    fn get_seen(&self, network: &str, nick: &str) -> Result<Seen, SeenError>;
+    fn list_seen(&self) -> Result<Vec<Seen>, SeenError>;
+    /// Writes the updates which were delayed, if the storage delays them.
+    fn save(&mut self) -> Result<(), SeenError>;
+}
+
+// HashMap
+impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<(String, String), Seen, S> {
+    fn insert_seen(&mut self, seen: &NewSeen) -> Result<(), SeenError> {
+        let seen = Seen {
+            network: seen.network.to_owned(),
+            nick: seen.nick.to_owned(),
+            name: seen.name.to_owned(),
+            activity: seen.activity.to_owned(),
+            channel: seen.channel.map(ToOwned::to_owned),
+            content: seen.content.map(ToOwned::to_owned),
+            time: seen.time,
+        };
+
+        self.insert((seen.network.clone(), seen.nick.clone()), seen);
+        Ok(())
+    }
+
+    fn get_seen(&self, network: &str, nick: &str) -> Result<Seen, SeenError> {
+        Ok(self
+            .get(&(network.to_owned(), nick.to_owned()))
+            .cloned()
+            .ok_or(ErrorKind::NotFound)?)
+    }
+
+    fn list_seen(&self) -> Result<Vec<Seen>, SeenError> {
+        Ok(self.values().cloned().collect())
+    }
+
+    fn save(&mut self) -> Result<(), SeenError> {
+        Ok(())
+    }
+}
+
+// File
+impl Database for FileDatabase<(String, String), Seen> {
+    fn insert_seen(&mut self, seen: &NewSeen) -> Result<(), SeenError> {
+        self.map_mut().insert_seen(seen)?;
+        Ok(self
+            .flush_lazily(MAX_UNSAVED, MAX_UNSAVED_AGE)
+            .context(ErrorKind::FileError)?)
+    }
+
+    fn get_seen(&self, network: &str, nick: &str) -> Result<Seen, SeenError> {
+        self.map().get_seen(network, nick)
+    }
+
+    fn list_seen(&self) -> Result<Vec<Seen>, SeenError> {
+        self.map().list_seen()
+    }
+
+    fn save(&mut self) -> Result<(), SeenError> {
+        Ok(self.flush_unsaved().context(ErrorKind::FileError)?)
+    }
+}
+
+// Box
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn insert_seen(&mut self, seen: &NewSeen) -> Result<(), SeenError> {
+        (**self).insert_seen(seen)
+    }
+
+    fn get_seen(&self, network: &str, nick: &str) -> Result<Seen, SeenError> {
+        (**self).get_seen(network, nick)
+    }
+
+    fn list_seen(&self) -> Result<Vec<Seen>, SeenError> {
+        (**self).list_seen()
+    }
+
+    fn save(&mut self) -> Result<(), SeenError> {
+        (**self).save()
+    }
+}
+
+// Diesel automatically defines the seen module as public.
+// We create a schema module to keep it private.
+#[cfg(feature = "sql")]
+mod schema {
+    table! {
+        seen (network, nick) {
+            network -> Varchar,
+            nick -> Varchar,
+            name -> Varchar,
+            activity -> Varchar,
+            channel -> Nullable<Varchar>,
+            content -> Nullable<Varchar>,
+            time -> Timestamp,
+        }
+    }
+}
+
+#[cfg(feature = "sql")]
+use self::schema::seen;
+
+// The queries are the same for every backend
+#[cfg(feature = "sql")]
+macro_rules! impl_database {
+    ($connection:ty, $error:ident) => {
+        impl Database for Arc<Pool<ConnectionManager<$connection>>> {
+            fn insert_seen(&mut self, new: &NewSeen) -> Result<(), SeenError> {
+                use self::seen::columns;
+
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let row = seen::table.find((new.network, new.nick));
+
+                // MySQL reports 0 affected rows if nothing changed so an update
+                // can not be used to detect whether the row exists
+                let exists = diesel::select(diesel::dsl::exists(row))
+                    .get_result::<bool>(conn)
+                    .context(ErrorKind::$error)?;
+
+                if exists {
+                    diesel::update(row)
+                        .set((
+                            columns::name.eq(new.name),
+                            columns::activity.eq(new.activity),
+                            columns::channel.eq(new.channel),
+                            columns::content.eq(new.content),
+                            columns::time.eq(new.time),
+                        ))
+                        .execute(conn)
+                        .context(ErrorKind::$error)?;
+                } else {
+                    diesel::insert_into(seen::table)
+                        .values(new)
+                        .execute(conn)
+                        .context(ErrorKind::$error)?;
+                }
+
+                Ok(())
+            }
+
+            fn get_seen(&self, network: &str, nick: &str) -> Result<Seen, SeenError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                match seen::table.find((network, nick)).first(conn) {
+                    Ok(seen) => Ok(seen),
+                    Err(diesel::NotFound) => Err(ErrorKind::NotFound)?,
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
+
+            fn list_seen(&self) -> Result<Vec<Seen>, SeenError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(seen::table.load::<Seen>(conn).context(ErrorKind::$error)?)
+            }
+
+            fn save(&mut self) -> Result<(), SeenError> {
+                Ok(())
+            }
+        }
+    };
+}
+
+#[cfg(feature = "mysql")]
+impl_database!(MysqlConnection, MysqlError);
+
+#[cfg(feature = "sqlite")]
+impl_database!(SqliteConnection, SqliteError);
+
+#[cfg(feature = "postgres")]
+impl_database!(PgConnection, PostgresError);
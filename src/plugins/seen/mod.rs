@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::sync::{Arc, Weak};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use antidote::RwLock;
+use irc::client::prelude::*;
+
+use chrono::{NaiveDateTime, Utc};
+use humantime::format_duration;
+
+use crate::plugin::*;
+use crate::FrippyClient;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::Fail;
+use failure::ResultExt;
+use log::{debug, error};
+
+use frippy_derive::PluginName;
+
+pub mod database;
+use self::database::{Database, NewSeen};
+
+/// Nick changes are only followed this many times.
+const MAX_CHAIN: usize = 5;
+/// Updates which the database delays are written at least this often.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(PluginName)]
+pub struct Seen<T: Database + 'static, C> {
+    seen: Arc<RwLock<T>>,
+    network: String,
+    phantom: PhantomData<C>,
+}
+
+/// Saves the delayed updates of the database until the plugin is dropped.
+fn save_periodically<T: Database>(db: Weak<RwLock<T>>) {
+    loop {
+        sleep(SAVE_INTERVAL);
+
+        let db = match db.upgrade() {
+            Some(db) => db,
+            None => return,
+        };
+
+        if let Err(e) = db.write().save() {
+            error!("Failed to save seen nicks: {}", e);
+        }
+    }
+}
+
+impl<T: Database + 'static, C: FrippyClient> Seen<T, C> {
+    /// Creates a `Seen` which stores activity under `network`,
+    /// so multiple networks can share a database.
+    pub fn new(db: T, network: &str) -> Self {
+        let seen = Arc::new(RwLock::new(db));
+        let weak = Arc::downgrade(&seen);
+        spawn(move || save_periodically(weak));
+
+        Seen {
+            seen,
+            network: network.to_lowercase(),
+            phantom: PhantomData,
+        }
+    }
+
+    fn record(
+        &self,
+        name: &str,
+        activity: &str,
+        channel: Option<&str>,
+        content: Option<&str>,
+    ) -> Result<(), SeenError> {
+        let seen = NewSeen {
+            network: &self.network,
+            nick: &name.to_lowercase(),
+            name,
+            activity,
+            channel,
+            content,
+            time: Utc::now().naive_utc(),
+        };
+
+        self.seen.write().insert_seen(&seen)
+    }
+
+    fn on_message(&self, message: &Message) -> Result<(), SeenError> {
+        let nick = match message.source_nickname() {
+            Some(nick) => nick,
+            None => return Ok(()),
+        };
+
+        match message.command {
+            // Queries are private
+            Command::PRIVMSG(ref target, ref content) if target.starts_with('#') => {
+                self.record(nick, "message", Some(target), Some(content))
+            }
+            Command::JOIN(ref channel, _, _) => self.record(nick, "join", Some(channel), None),
+            Command::PART(ref channel, ref reason) => {
+                self.record(nick, "part", Some(channel), reason.as_ref().map(|s| &s[..]))
+            }
+            Command::QUIT(ref reason) => {
+                self.record(nick, "quit", None, reason.as_ref().map(|s| &s[..]))
+            }
+            Command::NICK(ref new_nick) => {
+                self.record(nick, "nick", None, Some(new_nick))?;
+                self.record(new_nick, "renamed", None, Some(nick))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn seen_command(&self, client: &C, command: &PluginCommand) -> Result<String, SeenError> {
+        let nick = &command.tokens[0];
+        if nick.eq_ignore_ascii_case(client.current_nickname()) {
+            return Ok(String::from("I'm right here."));
+        } else if nick.eq_ignore_ascii_case(&command.source) {
+            return Ok(String::from("You are right here."));
+        }
+
+        let mut seen = match self
+            .seen
+            .read()
+            .get_seen(&self.network, &nick.to_lowercase())
+        {
+            Ok(seen) => seen,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(format!("I have not seen {}.", nick))
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Follow nick changes as long as the new nick was active afterwards
+        let mut sentences = Vec::new();
+        let mut visited = HashSet::new();
+        visited.insert(seen.nick.clone());
+        loop {
+            sentences.push(format!(
+                "{} was last seen {} ago {}.",
+                seen.name,
+                ago(seen.time),
+                describe(&seen)
+            ));
+
+            if seen.activity != "nick" || sentences.len() >= MAX_CHAIN {
+                break;
+            }
+
+            let new_nick = match seen.content {
+                Some(ref new_nick) => new_nick.to_lowercase(),
+                None => break,
+            };
+
+            // Nick changes can go in a circle
+            if !visited.insert(new_nick.clone()) {
+                break;
+            }
+
+            let next = match self.seen.read().get_seen(&self.network, &new_nick) {
+                Ok(next) => next,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => break,
+                Err(e) => return Err(e),
+            };
+
+            // Nothing new happened after the nick change
+            if next.time < seen.time || next.activity == "renamed" {
+                break;
+            }
+
+            debug!("Following nick change from {} to {}", seen.nick, next.nick);
+            seen = next;
+        }
+
+        Ok(sentences.join(" "))
+    }
+
+    fn invalid_command(&self) -> &str {
+        "Incorrect Command. \
+         Send \"seen help\" for help."
+    }
+
+    fn help(&self) -> &str {
+        "Shows when a user was last active.\r\n\
+         usage: seen <nick>\r\n\
+         example: seen Foobar"
+    }
+}
+
+fn ago(time: NaiveDateTime) -> String {
+    let secs = (Utc::now().naive_utc() - time).num_seconds().max(0);
+    format_duration(Duration::from_secs(secs as u64)).to_string()
+}
+
+fn describe(seen: &database::Seen) -> String {
+    let channel = seen.channel.as_ref().map_or("", |s| &s[..]);
+    let content = seen.content.as_ref().map_or("", |s| &s[..]);
+
+    match seen.activity.as_ref() {
+        "message" => format!("saying \"{}\" in {}", content, channel),
+        "join" => format!("joining {}", channel),
+        "part" if content.is_empty() => format!("leaving {}", channel),
+        "part" => format!("leaving {} ({})", channel, content),
+        "quit" if content.is_empty() => String::from("quitting"),
+        "quit" => format!("quitting ({})", content),
+        "nick" => format!("changing their nick to {}", content),
+        "renamed" => format!("changing their nick from {}", content),
+        activity => format!("doing {}", activity),
+    }
+}
+
+impl<T: Database, C: FrippyClient> Plugin for Seen<T, C> {
+    type Client = C;
+    fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
+        match self.on_message(message) {
+            Ok(_) => ExecutionStatus::Done,
+            Err(e) => ExecutionStatus::Err(e.context(FrippyErrorKind::Seen).into()),
+        }
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Seen should not use threading")
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        let response = match command.tokens.get(0).map(String::as_str) {
+            None | Some("") => self.invalid_command().to_owned(),
+            Some("help") => self.help().to_owned(),
+            Some(_) => self
+                .seen_command(client, &command)
+                .context(FrippyErrorKind::Seen)?,
+        };
+
+        client
+            .send_privmsg(&command.target, &response)
+            .context(FrippyErrorKind::Connection)?;
+
+        Ok(())
+    }
+
+    fn evaluate(&self, client: &Self::Client, command: PluginCommand) -> Result<String, String> {
+        if command.tokens.is_empty() {
+            return Err(self.invalid_command().to_owned());
+        }
+
+        self.seen_command(client, &command)
+            .map_err(|e| e.to_string())
+    }
+}
+
+use std::fmt;
+impl<T: Database, C: FrippyClient> fmt::Debug for Seen<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Seen {{ ... }}")
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "SeenError"]
+    pub enum ErrorKind {
+        /// Not found error
+        #[fail(display = "The nick was not seen")]
+        NotFound,
+
+        /// File error
+        #[fail(display = "Failed to save to the data file")]
+        FileError,
+
+        /// MySQL error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "Failed to execute MySQL Query")]
+        MysqlError,
+
+        /// SQLite error
+        #[cfg(feature = "sqlite")]
+        #[fail(display = "Failed to execute SQLite Query")]
+        SqliteError,
+
+        /// PostgreSQL error
+        #[cfg(feature = "postgres")]
+        #[fail(display = "Failed to execute PostgreSQL Query")]
+        PostgresError,
+
+        /// No connection error
+        #[cfg(feature = "sql")]
+        #[fail(display = "No connection to the database")]
+        NoConnection,
+    }
+}
@@ -1,25 +1,30 @@
 use std::collections::HashMap;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use std::sync::Arc;
 
 #[cfg(feature = "mysql")]
 use diesel::mysql::MysqlConnection;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+#[cfg(feature = "sql")]
 use diesel::prelude::*;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+#[cfg(feature = "sql")]
 use r2d2::Pool;
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use r2d2_diesel::ConnectionManager;
 
 use chrono::NaiveDateTime;
 
-#[cfg(feature = "mysql")]
 use failure::ResultExt;
+use serde::{Deserialize, Serialize};
 
 use super::error::*;
+use crate::storage::FileDatabase;
 
-#[cfg_attr(feature = "mysql", derive(Queryable))]
-#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "sql", derive(Queryable))]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct TellMessage {
     pub id: i64,
     pub sender: String,
@@ -28,8 +33,8 @@ pub struct TellMessage {
     pub message: String,
 }
 
-#[cfg_attr(feature = "mysql", derive(Insertable))]
-#[cfg_attr(feature = "mysql", table_name = "tells")]
+#[cfg_attr(feature = "sql", derive(Insertable))]
+#[cfg_attr(feature = "sql", table_name = "tells")]
 pub struct NewTellMessage<'a> {
     pub sender: &'a str,
     pub receiver: &'a str,
@@ -42,6 +47,7 @@ pub trait Database: Send + Sync {
     fn get_tells(&self, receiver: &str) -> Result<Vec<TellMessage>, TellError>;
     fn get_receivers(&self) -> Result<Vec<String>, TellError>;
     fn delete_tells(&mut self, receiver: &str) -> Result<(), TellError>;
+    fn list_tells(&self) -> Result<Vec<TellMessage>, TellError>;
 }
 
 // HashMap
@@ -81,11 +87,63 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, Vec
             None => Err(ErrorKind::NotFound)?,
         }
     }
+
+    fn list_tells(&self) -> Result<Vec<TellMessage>, TellError> {
+        Ok(self.values().flatten().cloned().collect())
+    }
+}
+
+// File
+impl Database for FileDatabase<String, Vec<TellMessage>> {
+    fn insert_tell(&mut self, tell: &NewTellMessage) -> Result<(), TellError> {
+        self.map_mut().insert_tell(tell)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn get_tells(&self, receiver: &str) -> Result<Vec<TellMessage>, TellError> {
+        self.map().get_tells(receiver)
+    }
+
+    fn get_receivers(&self) -> Result<Vec<String>, TellError> {
+        self.map().get_receivers()
+    }
+
+    fn delete_tells(&mut self, receiver: &str) -> Result<(), TellError> {
+        self.map_mut().delete_tells(receiver)?;
+        Ok(self.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn list_tells(&self) -> Result<Vec<TellMessage>, TellError> {
+        self.map().list_tells()
+    }
+}
+
+// Box
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn insert_tell(&mut self, tell: &NewTellMessage) -> Result<(), TellError> {
+        (**self).insert_tell(tell)
+    }
+
+    fn get_tells(&self, receiver: &str) -> Result<Vec<TellMessage>, TellError> {
+        (**self).get_tells(receiver)
+    }
+
+    fn get_receivers(&self) -> Result<Vec<String>, TellError> {
+        (**self).get_receivers()
+    }
+
+    fn delete_tells(&mut self, receiver: &str) -> Result<(), TellError> {
+        (**self).delete_tells(receiver)
+    }
+
+    fn list_tells(&self) -> Result<Vec<TellMessage>, TellError> {
+        (**self).list_tells()
+    }
 }
 
 // Diesel automatically defines the tells module as public.
 // We create a schema module to keep it private.
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 mod schema {
     table! {
         tells (id) {
@@ -98,53 +156,79 @@ mod schema {
     }
 }
 
-#[cfg(feature = "mysql")]
+#[cfg(feature = "sql")]
 use self::schema::tells;
 
-#[cfg(feature = "mysql")]
-impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
-    fn insert_tell(&mut self, tell: &NewTellMessage) -> Result<(), TellError> {
-        let conn = &*self.get().expect("Failed to get connection");
-        diesel::insert_into(tells::table)
-            .values(tell)
-            .execute(conn)
-            .context(ErrorKind::MysqlError)?;
-
-        Ok(())
-    }
-
-    fn get_tells(&self, receiver: &str) -> Result<Vec<TellMessage>, TellError> {
-        use self::tells::columns;
-
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let result = tells::table
-            .filter(columns::receiver.eq(receiver))
-            .order(columns::time.asc())
-            .load::<TellMessage>(conn)
-            .context(ErrorKind::MysqlError)?;
-
-        Ok(result)
-    }
-
-    fn get_receivers(&self) -> Result<Vec<String>, TellError> {
-        use self::tells::columns;
-
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        let result = tells::table
-            .select(columns::receiver)
-            .load::<String>(conn)
-            .context(ErrorKind::MysqlError)?;
+// The queries are the same for every backend
+#[cfg(feature = "sql")]
+macro_rules! impl_database {
+    ($connection:ty, $error:ident) => {
+        impl Database for Arc<Pool<ConnectionManager<$connection>>> {
+            fn insert_tell(&mut self, tell: &NewTellMessage) -> Result<(), TellError> {
+                let conn = &*self.get().expect("Failed to get connection");
+                diesel::insert_into(tells::table)
+                    .values(tell)
+                    .execute(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(())
+            }
+
+            fn get_tells(&self, receiver: &str) -> Result<Vec<TellMessage>, TellError> {
+                use self::tells::columns;
+
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let result = tells::table
+                    .filter(columns::receiver.eq(receiver))
+                    .order(columns::time.asc())
+                    .load::<TellMessage>(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(result)
+            }
+
+            fn get_receivers(&self) -> Result<Vec<String>, TellError> {
+                use self::tells::columns;
+
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let result = tells::table
+                    .select(columns::receiver)
+                    .load::<String>(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(result)
+            }
+
+            fn delete_tells(&mut self, receiver: &str) -> Result<(), TellError> {
+                use self::tells::columns;
+
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                diesel::delete(tells::table.filter(columns::receiver.eq(receiver)))
+                    .execute(conn)
+                    .context(ErrorKind::$error)?;
+                Ok(())
+            }
+
+            fn list_tells(&self) -> Result<Vec<TellMessage>, TellError> {
+                use self::tells::columns;
+
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let result = tells::table
+                    .order(columns::time.asc())
+                    .load::<TellMessage>(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(result)
+            }
+        }
+    };
+}
 
-        Ok(result)
-    }
+#[cfg(feature = "mysql")]
+impl_database!(MysqlConnection, MysqlError);
 
-    fn delete_tells(&mut self, receiver: &str) -> Result<(), TellError> {
-        use self::tells::columns;
+#[cfg(feature = "sqlite")]
+impl_database!(SqliteConnection, SqliteError);
 
-        let conn = &*self.get().context(ErrorKind::NoConnection)?;
-        diesel::delete(tells::table.filter(columns::receiver.eq(receiver)))
-            .execute(conn)
-            .context(ErrorKind::MysqlError)?;
-        Ok(())
-    }
-}
+#[cfg(feature = "postgres")]
+impl_database!(PgConnection, PostgresError);
@@ -0,0 +1,110 @@
+//! A small cache so links which are posted repeatedly are only downloaded once.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    created: Instant,
+    last_used: u64,
+}
+
+/// Keeps up to `capacity` titles for `ttl`,
+/// the least recently used title is dropped when it is full.
+#[derive(Debug)]
+pub struct TitleCache<V> {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<String, Entry<V>>,
+    uses: u64,
+}
+
+impl<V: Clone> TitleCache<V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        TitleCache {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            uses: 0,
+        }
+    }
+
+    pub fn get(&mut self, url: &str) -> Option<V> {
+        let expired = self.entries.get(url)?.created.elapsed() > self.ttl;
+        if expired {
+            self.entries.remove(url);
+            return None;
+        }
+
+        self.uses += 1;
+        let entry = self.entries.get_mut(url)?;
+        entry.last_used = self.uses;
+
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&mut self, url: &str, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if !self.entries.contains_key(url) && self.entries.len() >= self.capacity {
+            let ttl = self.ttl;
+            self.entries.retain(|_, e| e.created.elapsed() <= ttl);
+        }
+
+        if !self.entries.contains_key(url) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(url, _)| url.clone());
+
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.uses += 1;
+        self.entries.insert(
+            url.to_owned(),
+            Entry {
+                value,
+                created: Instant::now(),
+                last_used: self.uses,
+            },
+        );
+    }
+}
+
+/// Removes the parts of a URL which do not change the linked page,
+/// like the fragment and tracking parameters.
+pub fn normalize(url: &str) -> String {
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_owned(),
+    };
+
+    parsed.set_fragment(None);
+
+    // Rewriting the query could change the encoding of other parameters
+    if !parsed.query_pairs().any(|(key, _)| key.starts_with("utm_")) {
+        return parsed.into_string();
+    }
+
+    let pairs = parsed
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    if pairs.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    parsed.into_string()
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+#[cfg(feature = "sql")]
+use std::sync::Arc;
+
+#[cfg(feature = "mysql")]
+use diesel::mysql::MysqlConnection;
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+#[cfg(feature = "sql")]
+use r2d2::Pool;
+#[cfg(feature = "sql")]
+use r2d2_diesel::ConnectionManager;
+
+use chrono::NaiveDateTime;
+
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+
+use super::error::*;
+use crate::storage::error::StorageError;
+use crate::storage::FileDatabase;
+#[cfg(feature = "sql")]
+use crate::utils::escape_like;
+
+/// Without a database only the newest posts are kept,
+/// otherwise the file which is rewritten on every post would keep growing.
+const MAX_POSTS: i64 = 10_000;
+
+/// A URL which was posted in a channel.
+///
+/// `url` is normalized so reposts with a different fragment are found as well.
+#[cfg_attr(feature = "sql", derive(Queryable))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Post {
+    pub id: i64,
+    pub network: String,
+    pub channel: String,
+    pub nick: String,
+    pub url: String,
+    pub title: Option<String>,
+    pub time: NaiveDateTime,
+}
+
+#[cfg_attr(feature = "sql", derive(Insertable))]
+#[cfg_attr(feature = "sql", table_name = "url_posts")]
+pub struct NewPost<'a> {
+    pub network: &'a str,
+    pub channel: &'a str,
+    pub nick: &'a str,
+    pub url: &'a str,
+    pub title: Option<&'a str>,
+    pub time: NaiveDateTime,
+}
+
+pub trait Database: Send + Sync {
+    fn insert_post(&mut self, post: &NewPost) -> Result<(), UrlError>;
+    /// Returns the earliest post of `url` in the channel.
+    fn get_first_post(&self, network: &str, channel: &str, url: &str) -> Result<Post, UrlError>;
+    /// Returns up to `limit` posts whose URL or title contains `query`, newest first.
+    /// Without a channel the posts of every channel of the network are searched.
+    fn search_posts(
+        &self,
+        network: &str,
+        channel: Option<&str>,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<Post>, UrlError>;
+    fn list_posts(&self) -> Result<Vec<Post>, UrlError>;
+}
+
+// HashMap
+/// The posts which are kept in memory when no database is configured.
+///
+/// Only the newest `MAX_POSTS` posts are kept.
+#[derive(Debug, Default)]
+pub struct PostMap {
+    posts: HashMap<i64, Post>,
+    next_id: i64,
+}
+
+impl PostMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Adds the post with the next id and forgets the oldest posts once there are too many.
+fn insert_next(posts: &mut HashMap<i64, Post>, next_id: &mut i64, post: &NewPost) {
+    let id = *next_id;
+    *next_id += 1;
+
+    let post = Post {
+        id,
+        network: post.network.to_owned(),
+        channel: post.channel.to_owned(),
+        nick: post.nick.to_owned(),
+        url: post.url.to_owned(),
+        title: post.title.map(ToOwned::to_owned),
+        time: post.time,
+    };
+    posts.insert(id, post);
+
+    // Pruning in batches keeps the inserts cheap
+    if posts.len() as i64 > MAX_POSTS + MAX_POSTS / 10 {
+        let oldest = *next_id - MAX_POSTS;
+        posts.retain(|&id, _| id >= oldest);
+    }
+}
+
+fn first_post(
+    posts: &HashMap<i64, Post>,
+    network: &str,
+    channel: &str,
+    url: &str,
+) -> Result<Post, UrlError> {
+    Ok(posts
+        .values()
+        .filter(|p| p.network == network && p.channel == channel && p.url == url)
+        .min_by_key(|p| (p.time, p.id))
+        .cloned()
+        .ok_or(ErrorKind::NotFound)?)
+}
+
+fn search(
+    posts: &HashMap<i64, Post>,
+    network: &str,
+    channel: Option<&str>,
+    query: &str,
+    limit: i64,
+) -> Vec<Post> {
+    let query = query.to_lowercase();
+    let mut posts = posts
+        .values()
+        .filter(|p| p.network == network && channel.map_or(true, |c| p.channel == c))
+        .filter(|p| {
+            p.url.to_lowercase().contains(&query)
+                || p.title
+                    .as_ref()
+                    .map_or(false, |t| t.to_lowercase().contains(&query))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    posts.sort_by(|a, b| (b.time, b.id).cmp(&(a.time, a.id)));
+    posts.truncate(limit as usize);
+
+    posts
+}
+
+impl Database for PostMap {
+    fn insert_post(&mut self, post: &NewPost) -> Result<(), UrlError> {
+        insert_next(&mut self.posts, &mut self.next_id, post);
+        Ok(())
+    }
+
+    fn get_first_post(&self, network: &str, channel: &str, url: &str) -> Result<Post, UrlError> {
+        first_post(&self.posts, network, channel, url)
+    }
+
+    fn search_posts(
+        &self,
+        network: &str,
+        channel: Option<&str>,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<Post>, UrlError> {
+        Ok(search(&self.posts, network, channel, query, limit))
+    }
+
+    fn list_posts(&self) -> Result<Vec<Post>, UrlError> {
+        Ok(self.posts.values().cloned().collect())
+    }
+}
+
+// File
+/// A `PostMap` which is saved as a JSON file.
+#[derive(Debug)]
+pub struct PostFile {
+    file: FileDatabase<i64, Post>,
+    next_id: i64,
+}
+
+impl PostFile {
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, StorageError> {
+        let file = FileDatabase::open(path)?;
+        let next_id = file.map().keys().max().map_or(0, |id| id + 1);
+
+        Ok(PostFile { file, next_id })
+    }
+
+    /// See [`FileDatabase::begin_batch()`](../../../storage/struct.FileDatabase.html#method.begin_batch).
+    pub fn begin_batch(&mut self) {
+        self.file.begin_batch();
+    }
+
+    pub fn end_batch(&mut self) -> Result<(), StorageError> {
+        self.file.end_batch()
+    }
+}
+
+impl Database for PostFile {
+    fn insert_post(&mut self, post: &NewPost) -> Result<(), UrlError> {
+        insert_next(self.file.map_mut(), &mut self.next_id, post);
+        Ok(self.file.flush().context(ErrorKind::FileError)?)
+    }
+
+    fn get_first_post(&self, network: &str, channel: &str, url: &str) -> Result<Post, UrlError> {
+        first_post(self.file.map(), network, channel, url)
+    }
+
+    fn search_posts(
+        &self,
+        network: &str,
+        channel: Option<&str>,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<Post>, UrlError> {
+        Ok(search(self.file.map(), network, channel, query, limit))
+    }
+
+    fn list_posts(&self) -> Result<Vec<Post>, UrlError> {
+        Ok(self.file.map().values().cloned().collect())
+    }
+}
+
+// Box
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn insert_post(&mut self, post: &NewPost) -> Result<(), UrlError> {
+        (**self).insert_post(post)
+    }
+
+    fn get_first_post(&self, network: &str, channel: &str, url: &str) -> Result<Post, UrlError> {
+        (**self).get_first_post(network, channel, url)
+    }
+
+    fn search_posts(
+        &self,
+        network: &str,
+        channel: Option<&str>,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<Post>, UrlError> {
+        (**self).search_posts(network, channel, query, limit)
+    }
+
+    fn list_posts(&self) -> Result<Vec<Post>, UrlError> {
+        (**self).list_posts()
+    }
+}
+
+// Diesel automatically defines the url_posts module as public.
+// We create a schema module to keep it private.
+#[cfg(feature = "sql")]
+mod schema {
+    table! {
+        url_posts (id) {
+            id -> Bigint,
+            network -> Varchar,
+            channel -> Varchar,
+            nick -> Varchar,
+            url -> Text,
+            title -> Nullable<Varchar>,
+            time -> Timestamp,
+        }
+    }
+}
+
+#[cfg(feature = "sql")]
+use self::schema::url_posts;
+
+// LIKE is case-sensitive on Postgres and SQLite, the title needs its own
+// function because it is nullable.
+#[cfg(feature = "sql")]
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+#[cfg(feature = "sql")]
+sql_function! {
+    #[sql_name = "lower"]
+    fn lower_nullable(
+        x: diesel::sql_types::Nullable<diesel::sql_types::Text>
+    ) -> diesel::sql_types::Nullable<diesel::sql_types::Text>;
+}
+
+// The queries are the same for every backend
+#[cfg(feature = "sql")]
+macro_rules! impl_database {
+    ($connection:ty, $error:ident) => {
+        impl Database for Arc<Pool<ConnectionManager<$connection>>> {
+            fn insert_post(&mut self, post: &NewPost) -> Result<(), UrlError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                diesel::insert_into(url_posts::table)
+                    .values(post)
+                    .execute(conn)
+                    .context(ErrorKind::$error)?;
+
+                Ok(())
+            }
+
+            fn get_first_post(
+                &self,
+                network: &str,
+                channel: &str,
+                url: &str,
+            ) -> Result<Post, UrlError> {
+                use self::url_posts::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                match url_posts::table
+                    .filter(columns::network.eq(network))
+                    .filter(columns::channel.eq(channel))
+                    .filter(columns::url.eq(url))
+                    .order((columns::time.asc(), columns::id.asc()))
+                    .first(conn)
+                {
+                    Ok(post) => Ok(post),
+                    Err(diesel::NotFound) => Err(ErrorKind::NotFound)?,
+                    Err(e) => Err(e).context(ErrorKind::$error)?,
+                }
+            }
+
+            fn search_posts(
+                &self,
+                network: &str,
+                channel: Option<&str>,
+                query: &str,
+                limit: i64,
+            ) -> Result<Vec<Post>, UrlError> {
+                use self::url_posts::columns;
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+                let pattern = format!("%{}%", escape_like(&query.to_lowercase()));
+
+                let mut posts = url_posts::table
+                    .filter(columns::network.eq(network))
+                    .filter(
+                        lower(columns::url)
+                            .like(&pattern)
+                            .escape('\\')
+                            .or(lower_nullable(columns::title).like(&pattern).escape('\\')),
+                    )
+                    .into_boxed();
+
+                if let Some(channel) = channel {
+                    posts = posts.filter(columns::channel.eq(channel));
+                }
+
+                Ok(posts
+                    .order((columns::time.desc(), columns::id.desc()))
+                    .limit(limit)
+                    .load::<Post>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+
+            fn list_posts(&self) -> Result<Vec<Post>, UrlError> {
+                let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+                Ok(url_posts::table
+                    .load::<Post>(conn)
+                    .context(ErrorKind::$error)?)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "mysql")]
+impl_database!(MysqlConnection, MysqlError);
+
+#[cfg(feature = "sqlite")]
+impl_database!(SqliteConnection, SqliteError);
+
+#[cfg(feature = "postgres")]
+impl_database!(PgConnection, PostgresError);
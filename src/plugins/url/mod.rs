@@ -0,0 +1,662 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use antidote::{Mutex, RwLock};
+use irc::client::prelude::*;
+
+use chrono::{NaiveDateTime, Utc};
+
+use encoding_rs::{Encoding, UTF_8};
+use lazy_static::lazy_static;
+use regex::bytes::Regex as BytesRegex;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde_json::Value;
+
+use crate::plugin::*;
+use crate::utils::{Download, Http, Pager, Response, Url};
+use crate::FrippyClient;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::Fail;
+use failure::ResultExt;
+use log::{debug, error};
+
+use frippy_derive::PluginName;
+
+mod cache;
+pub mod database;
+mod rules;
+mod summary;
+use self::cache::TitleCache;
+use self::database::{Database, NewPost, Post};
+use self::rules::Rules;
+
+/// Titles are kept this long so links pasted in several channels are downloaded once.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+const CACHE_CAPACITY: usize = 256;
+/// The size of the title column, longer titles are cut off before storing them.
+const MAX_STORED_TITLE_CHARS: usize = 512;
+const MAX_SEARCH_RESULTS: i64 = 30;
+/// Longer than the matches of `HEAD_END_RE` without unusual whitespace.
+const HEAD_END_LEN: usize = 16;
+
+lazy_static! {
+    static ref URL_RE: Regex = Regex::new(r"(^|\s)(https?://\S+)").unwrap();
+    static ref WORD_RE: Regex = Regex::new(r"(\w+)").unwrap();
+    static ref HEADER_CHARSET_RE: Regex = Regex::new(r#"(?i)charset\s*=\s*"?([\w.:-]+)"#).unwrap();
+    static ref META_CHARSET_RE: BytesRegex =
+        BytesRegex::new(r#"(?i-u)<meta[^>]+charset\s*=\s*["']?([\w.:-]+)"#).unwrap();
+    static ref HEAD_END_RE: BytesRegex = BytesRegex::new(r"(?i-u)</head\s*>|<body[\s>]").unwrap();
+    static ref TITLE_SELECTOR: Selector = Selector::parse("title").unwrap();
+    static ref JSON_LD_SELECTOR: Selector =
+        Selector::parse(r#"script[type="application/ld+json"]"#).unwrap();
+    /// Both attributes are used for either title in the wild
+    static ref META_SELECTORS: [Selector; 2] = [
+        Selector::parse(r#"meta[property="og:title"], meta[name="og:title"]"#).unwrap(),
+        Selector::parse(r#"meta[name="twitter:title"], meta[property="twitter:title"]"#).unwrap(),
+    ];
+}
+
+#[derive(PluginName)]
+pub struct UrlTitles<T: Database, C> {
+    posts: Arc<RwLock<T>>,
+    network: String,
+    max_kib: usize,
+    cache: Mutex<TitleCache<Resolved>>,
+    http: Http,
+    phantom: PhantomData<C>,
+}
+
+/// A title together with the host the link ended up on.
+#[derive(Clone, Debug)]
+struct Resolved {
+    title: String,
+    host: String,
+}
+
+#[derive(Clone, Debug)]
+struct Title(String, Option<usize>);
+
+impl From<String> for Title {
+    fn from(title: String) -> Self {
+        Title(title, None)
+    }
+}
+
+impl From<Title> for String {
+    fn from(title: Title) -> Self {
+        title.0
+    }
+}
+
+impl Title {
+    // TODO Improve logic
+    fn get_usefulness(self, url: &str) -> Self {
+        let mut usefulness = 0;
+        for word in WORD_RE.find_iter(&self.0) {
+            let w = word.as_str().to_lowercase();
+            if w.len() > 2 && !url.to_lowercase().contains(&w) {
+                usefulness += 1;
+            }
+        }
+
+        Title(self.0, Some(usefulness))
+    }
+
+    pub fn usefulness(&self) -> usize {
+        self.1.expect("Usefulness should be calculated already")
+    }
+
+    fn clean_up(self) -> Self {
+        let title = self.0.split_whitespace().collect::<Vec<_>>().join(" ");
+        Title(title, self.1)
+    }
+
+    /// Collects every title of the document, the preferred ones first.
+    fn find_all(document: &Html) -> Vec<Self> {
+        let mut titles = Vec::new();
+
+        for selector in META_SELECTORS.iter() {
+            titles.extend(
+                document
+                    .select(selector)
+                    .filter_map(|e| e.value().attr("content"))
+                    .map(|t| Title::from(t.to_owned())),
+            );
+        }
+
+        titles.extend(
+            document
+                .select(&JSON_LD_SELECTOR)
+                .filter_map(|e| serde_json::from_str::<Value>(&e.text().collect::<String>()).ok())
+                .filter_map(|json| find_headline(&json))
+                .map(|t| Title::from(htmlescape::decode_html(&t).unwrap_or(t))),
+        );
+
+        titles.extend(
+            document
+                .select(&TITLE_SELECTOR)
+                .next()
+                .map(|e| Title::from(e.text().collect::<String>())),
+        );
+
+        titles
+    }
+
+    /// Returns the most useful title, preferring earlier ones if they are equally useful.
+    pub fn find_best(document: &Html, url: &str) -> Result<Self, UrlError> {
+        let mut best: Option<Self> = None;
+
+        for title in Self::find_all(document) {
+            let title = title.clean_up().get_usefulness(url);
+            debug!("Found title {:?}", title);
+
+            if title.0.is_empty() {
+                continue;
+            }
+
+            best = match best {
+                Some(best) if best.usefulness() >= title.usefulness() => Some(best),
+                _ => Some(title),
+            };
+        }
+
+        Ok(best.ok_or(ErrorKind::MissingTitle)?)
+    }
+}
+
+/// Finds the headline of JSON-LD data which can be nested in arrays or a `@graph`.
+fn find_headline(json: &Value) -> Option<String> {
+    match *json {
+        Value::Array(ref items) => items.iter().filter_map(find_headline).next(),
+        Value::Object(ref map) => match map.get("headline") {
+            Some(Value::String(headline)) => Some(headline.to_owned()),
+            _ => map.get("@graph").and_then(find_headline),
+        },
+        _ => None,
+    }
+}
+
+/// Reads the document until the end of its head where all titles are expected.
+fn read_head(response: Response) -> Result<Vec<u8>, UrlError> {
+    let mut bytes = Vec::new();
+
+    response
+        .stream(|chunk| {
+            // The end tag could be split between chunks
+            let searched = bytes.len().saturating_sub(HEAD_END_LEN);
+            bytes.extend_from_slice(chunk);
+
+            !HEAD_END_RE.is_match(&bytes[searched..])
+        })
+        .context(ErrorKind::Download)?;
+
+    Ok(bytes)
+}
+
+/// Decodes the body with the charset from the `Content-Type` header
+/// or the `<meta>` tags and falls back to UTF-8.
+fn decode(download: &Download) -> String {
+    let label = download
+        .content_type
+        .as_ref()
+        .and_then(|content_type| {
+            HEADER_CHARSET_RE
+                .captures(content_type)
+                .map(|c| c[1].as_bytes().to_owned())
+        })
+        .or_else(|| {
+            // The charset has to be declared near the start of the document
+            let head = &download.bytes[..download.bytes.len().min(1024)];
+            META_CHARSET_RE.captures(head).map(|c| c[1].to_owned())
+        });
+
+    let encoding = label
+        .and_then(|label| Encoding::for_label(&label))
+        .unwrap_or(UTF_8);
+    debug!("Decoding body as {}", encoding.name());
+
+    // A byte order mark overrides the declared charset
+    let (body, _, _) = encoding.decode(&download.bytes);
+    body.into_owned()
+}
+
+impl<T: Database, C: FrippyClient> UrlTitles<T, C> {
+    /// Creates a `UrlTitles` which stores the posted URLs under `network`.
+    /// If a file is larger than `max_kib` KiB the download is stopped
+    pub fn new(db: T, network: &str, max_kib: usize, http: Http) -> Self {
+        UrlTitles {
+            posts: Arc::new(RwLock::new(db)),
+            network: network.to_lowercase(),
+            max_kib,
+            cache: Mutex::new(TitleCache::new(CACHE_CAPACITY, CACHE_TTL)),
+            http,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Creates the plugin for the `urls` command which searches the same history.
+    pub fn history(&self) -> Urls<T, C> {
+        Urls {
+            posts: Arc::clone(&self.posts),
+            network: self.network.clone(),
+            pager: Pager::new(3, "urls more"),
+            phantom: PhantomData,
+        }
+    }
+
+    fn grep_urls<'a>(&self, msg: &'a str) -> Vec<&'a str> {
+        let mut urls = Vec::new();
+        for captures in URL_RE.captures_iter(msg) {
+            debug!("Url captures: {:?}", captures);
+            if let Some(url) = captures.get(2).map(|m| m.as_str()) {
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+        }
+
+        urls
+    }
+
+    fn url(&self, text: &str) -> Result<String, UrlError> {
+        let url = *self.grep_urls(text).first().ok_or(ErrorKind::MissingUrl)?;
+
+        Ok(self.title(url)?.title)
+    }
+
+    /// Returns the cached title or downloads it.
+    fn title(&self, url: &str) -> Result<Resolved, UrlError> {
+        let key = cache::normalize(url);
+        if let Some(resolved) = self.cache.lock().get(&key) {
+            debug!("Using cached title for {}", key);
+            return Ok(resolved);
+        }
+
+        let resolved = self.download_title(url)?;
+        self.cache.lock().insert(&key, resolved.clone());
+
+        Ok(resolved)
+    }
+
+    fn download_title(&self, url: &str) -> Result<Resolved, UrlError> {
+        let url = Url::from(url).max_kib(self.max_kib).http(&self.http);
+        let response = url.open().context(ErrorKind::Download)?;
+        // Redirects were followed already
+        let host = rules::host(&response.url).unwrap_or_default();
+
+        // Other files are described without downloading them completely
+        if !summary::is_html(response.content_type.as_ref().map(String::as_str)) {
+            let title = summary::summarize(response)?;
+            return Ok(Resolved { title, host });
+        }
+
+        let download = Download {
+            content_type: response.content_type.clone(),
+            bytes: read_head(response)?,
+        };
+
+        let document = Html::parse_document(&decode(&download));
+        let title = Title::find_best(&document, url.as_str())?;
+
+        if title.usefulness() == 0 {
+            Err(ErrorKind::UselessTitle)?;
+        }
+
+        Ok(Resolved {
+            title: title.into(),
+            host,
+        })
+    }
+
+    /// Resolves a single URL of a message and returns the line to send.
+    fn respond(
+        &self,
+        rules: &Rules,
+        target: &str,
+        nick: Option<&str>,
+        url: &str,
+    ) -> Result<Option<String>, UrlError> {
+        let host = rules::host(url).unwrap_or_default();
+        if !rules.allows(target, &host) {
+            debug!("Ignoring {} in {}", url, target);
+            return Ok(None);
+        }
+
+        let resolved = self.title(url);
+
+        // Queries are private
+        let first = match nick {
+            Some(nick) if target.starts_with('#') => {
+                let title = resolved.as_ref().ok().map(|r| r.title.as_str());
+                // The title is more important than the history
+                self.record(target, nick, url, title).unwrap_or_else(|e| {
+                    error!("Failed to record {}: {}", url, e);
+                    None
+                })
+            }
+            _ => None,
+        };
+
+        let resolved = resolved?;
+        // Shorteners could lead anywhere
+        if !rules.allows(target, &resolved.host) {
+            debug!("Ignoring {} which redirects to {}", url, resolved.host);
+            return Ok(None);
+        }
+
+        let mut response = match rules.template(&resolved.host) {
+            Some(template) => rules::apply_template(template, &resolved.title, &resolved.host, url),
+            None if rules.is_shortener(&host) && resolved.host != host => {
+                format!("[URL] {} ({})", resolved.title, resolved.host)
+            }
+            None => format!("[URL] {}", resolved.title),
+        };
+
+        if let Some(post) = first {
+            response.push_str(&format!(
+                " (posted by {} {} ago)",
+                post.nick,
+                ago(post.time)
+            ));
+        }
+
+        Ok(Some(response))
+    }
+
+    /// Adds the post to the history and returns the first post of the URL in the channel.
+    fn record(
+        &self,
+        channel: &str,
+        nick: &str,
+        url: &str,
+        title: Option<&str>,
+    ) -> Result<Option<Post>, UrlError> {
+        let url = cache::normalize(url);
+        let first = match self
+            .posts
+            .read()
+            .get_first_post(&self.network, channel, &url)
+        {
+            Ok(post) => Some(post),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let title = title.map(|t| match t.char_indices().nth(MAX_STORED_TITLE_CHARS) {
+            Some((end, _)) => &t[..end],
+            None => t,
+        });
+
+        let post = NewPost {
+            network: &self.network,
+            channel,
+            nick,
+            url: &url,
+            title,
+            time: Utc::now().naive_utc(),
+        };
+        self.posts.write().insert_post(&post)?;
+
+        Ok(first)
+    }
+}
+
+/// Formats the time since `time` in its largest unit like "3 days".
+fn ago(time: NaiveDateTime) -> String {
+    let secs = (Utc::now().naive_utc() - time).num_seconds().max(0);
+    let (amount, unit) = match secs {
+        s if s < 60 => (s, "second"),
+        s if s < 60 * 60 => (s / 60, "minute"),
+        s if s < 24 * 60 * 60 => (s / (60 * 60), "hour"),
+        s if s < 365 * 24 * 60 * 60 => (s / (24 * 60 * 60), "day"),
+        s => (s / (365 * 24 * 60 * 60), "year"),
+    };
+
+    if amount == 1 {
+        format!("{} {}", amount, unit)
+    } else {
+        format!("{} {}s", amount, unit)
+    }
+}
+
+impl<T: Database, C: FrippyClient> Plugin for UrlTitles<T, C> {
+    type Client = C;
+    fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
+        match message.command {
+            Command::PRIVMSG(_, ref msg) => {
+                if URL_RE.is_match(msg) {
+                    ExecutionStatus::RequiresThread
+                } else {
+                    ExecutionStatus::Done
+                }
+            }
+            _ => ExecutionStatus::Done,
+        }
+    }
+
+    fn execute_threaded(
+        &self,
+        client: &Self::Client,
+        message: &Message,
+    ) -> Result<(), FrippyError> {
+        if let Command::PRIVMSG(ref target, ref content) = message.command {
+            let rules = Rules::new(client.config());
+            let nick = message.source_nickname();
+            if nick.map_or(false, |nick| rules.ignores_nick(nick)) {
+                return Ok(());
+            }
+
+            let response_target = message.response_target().unwrap();
+            let mut result = Ok(());
+            for url in self.grep_urls(content).into_iter().take(rules.max_urls()) {
+                // One broken link should not hide the others
+                match self.respond(&rules, target, nick, url) {
+                    Ok(Some(response)) => client
+                        .send_privmsg(response_target, &response)
+                        .context(FrippyErrorKind::Connection)?,
+                    Ok(None) => (),
+                    Err(e) => {
+                        if result.is_ok() {
+                            result = Err(e);
+                        }
+                    }
+                }
+            }
+
+            result.context(FrippyErrorKind::Url)?;
+        }
+
+        Ok(())
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        client
+            .send_privmsg(
+                &command.target,
+                "This Plugin does not implement any commands.",
+            )
+            .context(FrippyErrorKind::Connection)?;
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, command: PluginCommand) -> Result<String, String> {
+        self.url(&command.tokens[0])
+            .map_err(|e| e.cause().unwrap().to_string())
+    }
+}
+
+use std::fmt;
+impl<T: Database, C: FrippyClient> fmt::Debug for UrlTitles<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UrlTitles {{ max_kib: {} }}", self.max_kib)
+    }
+}
+
+/// Searches the URLs which were posted before.
+#[derive(PluginName)]
+pub struct Urls<T: Database, C> {
+    posts: Arc<RwLock<T>>,
+    network: String,
+    pager: Pager,
+    phantom: PhantomData<C>,
+}
+
+impl<T: Database, C: FrippyClient> Urls<T, C> {
+    fn search(&self, client: &C, command: &PluginCommand) -> Result<Vec<String>, UrlError> {
+        let query = command.tokens[1..].join(" ");
+        if query.is_empty() {
+            return Ok(vec![self.invalid_command().to_owned()]);
+        }
+
+        // Queries search every channel, including secret ones
+        let channel = Some(command.target.as_str()).filter(|t| t.starts_with('#'));
+        if channel.is_none() && !client.is_owner_mask(&command.prefix) {
+            return Ok(vec![String::from(
+                "Only owners listed with their hostmask can search every channel.",
+            )]);
+        }
+        let posts =
+            self.posts
+                .read()
+                .search_posts(&self.network, channel, &query, MAX_SEARCH_RESULTS)?;
+
+        let lines = posts.into_iter().map(|post| {
+            let title = post.title.map(|t| format!(" - {}", t)).unwrap_or_default();
+            format!(
+                "{} {} in {}: {}{}",
+                post.time.format("%Y-%m-%d"),
+                post.nick,
+                post.channel,
+                post.url,
+                title
+            )
+        });
+
+        let page = self.pager.start(&command.source, lines);
+        if page.is_empty() {
+            Ok(vec![String::from("No matching URLs found.")])
+        } else {
+            Ok(page)
+        }
+    }
+
+    fn invalid_command(&self) -> &str {
+        "Incorrect Command. \
+         Send \"urls help\" for help."
+    }
+
+    fn help(&self) -> &str {
+        "usage: urls <subcommand>\r\n\
+         subcommands: search, more\r\n\
+         example: urls search rust"
+    }
+}
+
+impl<T: Database, C: FrippyClient> Plugin for Urls<T, C> {
+    type Client = C;
+    fn execute(&self, _: &Self::Client, _: &Message) -> ExecutionStatus {
+        ExecutionStatus::Done
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Urls should not use threading")
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        let lines = match command.tokens.get(0).map(String::as_str) {
+            Some("search") => self
+                .search(client, &command)
+                .context(FrippyErrorKind::Url)?,
+            Some("more") => {
+                let page = self.pager.next(&command.source);
+                if page.is_empty() {
+                    vec![String::from("There is nothing left to show.")]
+                } else {
+                    page
+                }
+            }
+            Some("help") => vec![self.help().to_owned()],
+            _ => vec![self.invalid_command().to_owned()],
+        };
+
+        for line in lines {
+            client
+                .send_privmsg(&command.target, &line)
+                .context(FrippyErrorKind::Connection)?;
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from(
+            "Evaluation of commands is not implemented for urls at this time",
+        ))
+    }
+}
+
+impl<T: Database, C: FrippyClient> fmt::Debug for Urls<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Urls {{ network: {:?} }}", self.network)
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    /// A URL plugin error
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "UrlError"]
+    pub enum ErrorKind {
+        /// A download error
+        #[fail(display = "A download error occured")]
+        Download,
+
+        /// Missing URL error
+        #[fail(display = "No URL was found")]
+        MissingUrl,
+
+        /// Missing title error
+        #[fail(display = "No title was found")]
+        MissingTitle,
+
+        /// Useless title error
+        #[fail(display = "The titles found were not useful enough")]
+        UselessTitle,
+
+        /// Not found error
+        #[fail(display = "The URL was not posted before")]
+        NotFound,
+
+        /// File error
+        #[fail(display = "Failed to save to the data file")]
+        FileError,
+
+        /// MySQL error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "Failed to execute MySQL Query")]
+        MysqlError,
+
+        /// SQLite error
+        #[cfg(feature = "sqlite")]
+        #[fail(display = "Failed to execute SQLite Query")]
+        SqliteError,
+
+        /// PostgreSQL error
+        #[cfg(feature = "postgres")]
+        #[fail(display = "Failed to execute PostgreSQL Query")]
+        PostgresError,
+
+        /// No connection error
+        #[cfg(feature = "sql")]
+        #[fail(display = "No connection to the database")]
+        NoConnection,
+    }
+}
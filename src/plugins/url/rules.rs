@@ -0,0 +1,129 @@
+//! Which links get a title and how it is shown, configured in `[options]`.
+//!
+//! Domain lists are comma separated and also match subdomains.
+//! Lists with a `:#channel` suffix only apply to that channel.
+use std::collections::HashMap;
+
+use reqwest::Url;
+
+use crate::Config;
+
+/// Used if `url_max_per_message` is not set.
+const DEFAULT_MAX_URLS: usize = 3;
+/// Used if `url_shorteners` is not set.
+const DEFAULT_SHORTENERS: &str = "bit.ly, t.co, goo.gl, tinyurl.com, ow.ly, is.gd, buff.ly, \
+                                  youtu.be, redd.it, amzn.to, lnkd.in";
+
+#[derive(Debug)]
+pub struct Rules<'a> {
+    options: Option<&'a HashMap<String, String>>,
+}
+
+impl<'a> Rules<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Rules {
+            options: config.options.as_ref(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.options.and_then(|o| o.get(key)).map(String::as_str)
+    }
+
+    fn list(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key).map(split_list)
+    }
+
+    /// The number of links in a single message which get a title.
+    pub fn max_urls(&self) -> usize {
+        self.get("url_max_per_message")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_URLS)
+    }
+
+    pub fn ignores_nick(&self, nick: &str) -> bool {
+        self.list("url_ignore_nicks").map_or(false, |nicks| {
+            nicks.iter().any(|n| n == &nick.to_lowercase())
+        })
+    }
+
+    /// Denied domains win over allowed ones.
+    /// If an allow list exists only its domains are allowed,
+    /// the list of a channel replaces the global one.
+    pub fn allows(&self, channel: &str, host: &str) -> bool {
+        let channel = channel.to_lowercase();
+        let list = |key: &str| {
+            self.list(&format!("{}:{}", key, channel))
+                .or_else(|| self.list(key))
+        };
+        let denied = |key: &str| self.list(key).map_or(false, |l| matches_any(&l, host));
+
+        if denied("url_deny_domains") || denied(&format!("url_deny_domains:{}", channel)) {
+            return false;
+        }
+
+        list("url_allow_domains").map_or(true, |l| matches_any(&l, host))
+    }
+
+    /// Links to these domains show where they redirect to.
+    pub fn is_shortener(&self, host: &str) -> bool {
+        let shorteners = self
+            .list("url_shorteners")
+            .unwrap_or_else(|| split_list(DEFAULT_SHORTENERS));
+
+        matches_any(&shorteners, host)
+    }
+
+    /// Returns the template of the most specific domain set with `url_template:<domain>`.
+    pub fn template(&self, host: &str) -> Option<&'a str> {
+        self.options?
+            .iter()
+            .filter_map(|(key, value)| {
+                if !key.starts_with("url_template:") {
+                    return None;
+                }
+
+                let domain = key["url_template:".len()..].to_lowercase();
+                if matches_domain(&domain, host) {
+                    Some((domain.len(), value.as_str()))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|&(len, _)| len)
+            .map(|(_, template)| template)
+    }
+}
+
+/// Fills in `{title}`, `{host}` and `{url}`.
+pub fn apply_template(template: &str, title: &str, host: &str, url: &str) -> String {
+    template
+        .replace("{host}", host)
+        .replace("{url}", url)
+        // Last so titles can not insert placeholders
+        .replace("{title}", title)
+}
+
+/// Returns the lowercase host of `url`.
+pub fn host(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(|h| h.trim_end_matches('.').to_lowercase())
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn matches_any(domains: &[String], host: &str) -> bool {
+    domains.iter().any(|d| matches_domain(d, host))
+}
+
+/// Matches the domain itself and its subdomains.
+fn matches_domain(domain: &str, host: &str) -> bool {
+    host == domain || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'))
+}
@@ -0,0 +1,238 @@
+//! Short descriptions of links which do not point to HTML documents.
+use encoding_rs::UTF_16BE;
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+
+use crate::utils::{self, Response};
+
+use super::error::*;
+use failure::ResultExt;
+
+/// Image dimensions and PDF metadata are expected within this many bytes.
+pub const SNIFF_LEN: usize = 64 * 1024;
+
+lazy_static! {
+    static ref PDF_TITLE_RE: Regex = Regex::new(r"(?s-u)/Title\s*\(((?:[^\\)]|\\.)*)\)").unwrap();
+}
+
+/// Returns whether the content type is a document which can contain a title.
+pub fn is_html(content_type: Option<&str>) -> bool {
+    content_type.map_or(true, |t| {
+        let t = t.to_lowercase();
+        t.contains("html") || t.contains("xml")
+    })
+}
+
+/// Describes the file with its type, details depending on the type and size,
+/// only the first bytes are downloaded if they are needed at all.
+pub fn summarize(response: Response) -> Result<String, UrlError> {
+    let mime = response
+        .content_type
+        .as_ref()
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim().to_lowercase())
+        .unwrap_or_default();
+    let size = response.content_length;
+    let file_name = file_name(&response.url);
+
+    let mut parts = Vec::new();
+    if !mime.is_empty() {
+        parts.push(mime.clone());
+    }
+
+    if mime.starts_with("image/") {
+        let head = response
+            .read_start(SNIFF_LEN)
+            .context(ErrorKind::Download)?;
+        if let Some((width, height)) = image_dimensions(&head) {
+            parts.push(format!("{}×{}", width, height));
+        }
+    } else if mime == "application/pdf" {
+        let head = response
+            .read_start(SNIFF_LEN)
+            .context(ErrorKind::Download)?;
+        match pdf_title(&head) {
+            Some(title) => parts.push(format!("\"{}\"", title)),
+            None => parts.extend(file_name),
+        }
+    } else {
+        parts.extend(file_name);
+    }
+
+    if let Some(size) = size {
+        parts.push(format_size(size));
+    }
+
+    if parts.is_empty() {
+        Err(ErrorKind::MissingTitle)?;
+    }
+
+    Ok(parts.join(", "))
+}
+
+fn file_name(url: &str) -> Option<String> {
+    let path = url.split(|c| c == '?' || c == '#').next()?;
+    let name = path.rsplit('/').next()?;
+
+    // A host or directory is not a file name
+    if name.is_empty() || path.ends_with(&format!("//{}", name)) {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn be_u16(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 2)?;
+    Some(u32::from(b[0]) << 8 | u32::from(b[1]))
+}
+
+fn le_u16(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 2)?;
+    Some(u32::from(b[1]) << 8 | u32::from(b[0]))
+}
+
+fn be_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(be_u16(bytes, offset)? << 16 | be_u16(bytes, offset + 2)?)
+}
+
+fn le_u24(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from(*bytes.get(offset + 2)?) << 16 | le_u16(bytes, offset)?)
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(le_u16(bytes, offset + 2)? << 16 | le_u16(bytes, offset)?)
+}
+
+/// Reads the dimensions from the header of PNG, GIF, JPEG, WebP and BMP files.
+fn image_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some((be_u32(head, 16)?, be_u32(head, 20)?))
+    } else if head.starts_with(b"GIF8") {
+        Some((le_u16(head, 6)?, le_u16(head, 8)?))
+    } else if head.starts_with(b"\xff\xd8") {
+        jpeg_dimensions(head)
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(&b"WEBP"[..]) {
+        webp_dimensions(head)
+    } else if head.starts_with(b"BM") {
+        // Negative heights are used for top-down bitmaps
+        let height = le_u32(head, 22)? as i32;
+        Some((le_u32(head, 18)?, height.wrapping_abs() as u32))
+    } else {
+        None
+    }
+}
+
+/// Walks the segments until a start of frame marker is found.
+fn jpeg_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    let mut offset = 2;
+
+    loop {
+        if *head.get(offset)? != 0xff {
+            return None;
+        }
+
+        let marker = *head.get(offset + 1)?;
+        match marker {
+            // Padding
+            0xff => offset += 1,
+            // Markers without a length
+            0x01 | 0xd0..=0xd7 => offset += 2,
+            // Start of frame, except for DHT, JPG and DAC
+            0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                return Some((be_u16(head, offset + 7)?, be_u16(head, offset + 5)?));
+            }
+            _ => offset += 2 + be_u16(head, offset + 2)? as usize,
+        }
+    }
+}
+
+fn webp_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    match head.get(12..16)? {
+        b"VP8 " => Some((le_u16(head, 26)? & 0x3fff, le_u16(head, 28)? & 0x3fff)),
+        b"VP8L" => {
+            let bits = le_u32(head, 21)?;
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        b"VP8X" => Some((le_u24(head, 24)? + 1, le_u24(head, 27)? + 1)),
+        _ => None,
+    }
+}
+
+/// Finds an unencrypted title in the document information dictionary.
+fn pdf_title(head: &[u8]) -> Option<String> {
+    let captures = PDF_TITLE_RE.captures(head)?;
+    let raw = unescape_pdf_string(&captures[1]);
+
+    let title = if raw.starts_with(b"\xfe\xff") {
+        UTF_16BE.decode(&raw).0.into_owned()
+    } else {
+        // PDFDocEncoding mostly matches Latin-1
+        raw.iter().map(|&b| char::from(b)).collect()
+    };
+
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let title = utils::strip_control_codes(&title);
+    let title = title.trim();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.to_owned())
+    }
+}
+
+fn unescape_pdf_string(raw: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(raw.len());
+    let mut bytes = raw.iter().cloned();
+
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            output.push(b);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(b'n') => output.push(b'\n'),
+            Some(b'r') => output.push(b'\r'),
+            Some(b't') => output.push(b'\t'),
+            Some(b'b') => output.push(0x08),
+            Some(b'f') => output.push(0x0c),
+            // Octal codes have up to three digits
+            Some(d @ b'0'..=b'7') => {
+                let mut value = u32::from(d - b'0');
+                let mut rest = bytes.clone();
+                for _ in 0..2 {
+                    match rest.next() {
+                        Some(d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            bytes.next();
+                        }
+                        _ => break,
+                    }
+                }
+                output.push(value as u8);
+            }
+            Some(b) => output.push(b),
+            None => break,
+        }
+    }
+
+    output
+}
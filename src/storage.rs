@@ -0,0 +1,179 @@
+//! Persistent storage for plugins which is used when no database is configured.
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use self::error::{ErrorKind, StorageError};
+use failure::ResultExt;
+use log::error;
+
+/// A `HashMap` which is saved as a JSON file.
+///
+/// Plugins call [`flush()`](#method.flush) after every change
+/// or [`flush_lazily()`](#method.flush_lazily) if they change it often.
+/// The file is replaced atomically by writing a temporary file first and
+/// renaming it afterwards so a crash can not leave a half written file behind.
+#[derive(Debug)]
+pub struct FileDatabase<K, V>
+where
+    K: Eq + Hash + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    path: PathBuf,
+    map: HashMap<K, V>,
+    /// The number of changes which were not written yet.
+    unsaved: usize,
+    last_flush: Instant,
+    /// Set while changes are collected to write them at once.
+    batch: bool,
+}
+
+impl<K, V> FileDatabase<K, V>
+where
+    K: Eq + Hash + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Loads the entries saved at `path`.
+    /// If the file does not exist yet the database starts out empty.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, StorageError> {
+        let path = path.into();
+
+        let map = match File::open(&path) {
+            Ok(file) => {
+                let entries: Vec<(K, V)> =
+                    serde_json::from_reader(BufReader::new(file)).context(ErrorKind::Parse)?;
+                entries.into_iter().collect()
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => Err(e).context(ErrorKind::Read)?,
+        };
+
+        Ok(FileDatabase {
+            path,
+            map,
+            unsaved: 0,
+            last_flush: Instant::now(),
+            batch: false,
+        })
+    }
+
+    /// Writes all entries to the file, during a batch the change is only counted.
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        if self.batch {
+            self.unsaved += 1;
+            return Ok(());
+        }
+
+        // Maps with non-string keys can not be represented as JSON objects
+        let entries = self.map.iter().collect::<Vec<_>>();
+        let json = serde_json::to_vec(&entries).context(ErrorKind::Write)?;
+
+        let mut tmp_path = OsString::from(self.path.as_os_str());
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = File::create(&tmp_path).context(ErrorKind::Write)?;
+        file.write_all(&json).context(ErrorKind::Write)?;
+        file.sync_all().context(ErrorKind::Write)?;
+
+        fs::rename(&tmp_path, &self.path).context(ErrorKind::Write)?;
+
+        self.unsaved = 0;
+        self.last_flush = Instant::now();
+
+        Ok(())
+    }
+
+    /// Counts a change and writes all entries to the file once there are
+    /// `max_unsaved` changes or the last write is older than `max_age`.
+    ///
+    /// Changes which were not written yet are written when the database is dropped.
+    pub fn flush_lazily(
+        &mut self,
+        max_unsaved: usize,
+        max_age: Duration,
+    ) -> Result<(), StorageError> {
+        self.unsaved += 1;
+        if self.unsaved >= max_unsaved || self.last_flush.elapsed() >= max_age {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes the changes which were delayed by `flush_lazily()`, if there are any.
+    pub fn flush_unsaved(&mut self) -> Result<(), StorageError> {
+        if self.unsaved > 0 {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Delays writing the file until [`end_batch()`](#method.end_batch)
+    /// is called, the changes are lost if that does not happen.
+    pub fn begin_batch(&mut self) {
+        self.batch = true;
+    }
+
+    /// Writes the changes made since [`begin_batch()`](#method.begin_batch).
+    pub fn end_batch(&mut self) -> Result<(), StorageError> {
+        self.batch = false;
+        if self.unsaved > 0 {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn map(&self) -> &HashMap<K, V> {
+        &self.map
+    }
+
+    pub fn map_mut(&mut self) -> &mut HashMap<K, V> {
+        &mut self.map
+    }
+}
+
+impl<K, V> Drop for FileDatabase<K, V>
+where
+    K: Eq + Hash + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    fn drop(&mut self) {
+        // Unfinished batches are discarded
+        if self.unsaved > 0 && !self.batch {
+            if let Err(e) = self.flush() {
+                error!("Failed to save {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "StorageError"]
+    pub enum ErrorKind {
+        /// Read error
+        #[fail(display = "Failed to read the data file")]
+        Read,
+
+        /// Parse error
+        #[fail(display = "Failed to parse the data file")]
+        Parse,
+
+        /// Write error
+        #[fail(display = "Failed to write the data file")]
+        Write,
+    }
+}
@@ -1,18 +1,308 @@
 use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, Read};
+use std::iter::Peekable;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
 use std::time::Duration;
 
-use reqwest::header::{HeaderValue, ACCEPT_LANGUAGE, CONNECTION};
-use reqwest::{Client, ClientBuilder};
+use antidote::Mutex;
+use lazy_static::lazy_static;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_LANGUAGE, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE,
+    HOST, TRANSFER_ENCODING, USER_AGENT,
+};
+use reqwest::{Client, ClientBuilder, Proxy, RedirectPolicy};
+use url::Host;
+
+use crate::config::HttpConfig;
 
 use self::error::{DownloadError, ErrorKind};
 use failure::ResultExt;
 
+/// Only the default ports of the schemes and common alternatives can be fetched.
+const ALLOWED_PORTS: [u16; 5] = [80, 443, 8000, 8080, 8443];
+const ALLOWED_SCHEMES: [&str; 2] = ["http", "https"];
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_USER_AGENT: &str = concat!("frippy/", env!("CARGO_PKG_VERSION"));
+const DEFAULT_ACCEPT_LANGUAGE: &str = "en-US,en;q=0.5";
+
+lazy_static! {
+    static ref DEFAULT_HTTP: Http = Http::new(&HttpConfig::default(), Policy::default())
+        .expect("the default HTTP config is valid");
+}
+
+/// The client which is shared by all downloads of a bot, so connections are reused.
+///
+/// It is configured by the `[http]` section of a config file.
+#[derive(Clone, Debug)]
+pub struct Http {
+    client: Client,
+    policy: Policy,
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        DEFAULT_HTTP.clone()
+    }
+}
+
+impl Http {
+    pub fn new(config: &HttpConfig, policy: Policy) -> Result<Self, DownloadError> {
+        let policy = match config.max_redirects {
+            Some(max) => policy.max_redirects(max),
+            None => policy,
+        };
+
+        // Redirects have to follow the same rules
+        let redirect_policy = policy.clone();
+        let redirects = RedirectPolicy::custom(move |attempt| {
+            if attempt.previous().len() >= redirect_policy.max_redirects {
+                attempt.too_many_redirects()
+            } else if let Err(e) = redirect_policy.check(attempt.url()) {
+                attempt.error(e.to_string())
+            } else {
+                attempt.follow()
+            }
+        });
+
+        let timeout = match config.timeout {
+            Some(ref timeout) => {
+                humantime::parse_duration(timeout).context(ErrorKind::InvalidConfig)?
+            }
+            None => DEFAULT_TIMEOUT,
+        };
+
+        let user_agent = config
+            .user_agent
+            .as_ref()
+            .map_or(DEFAULT_USER_AGENT, String::as_str);
+        let accept_language = config
+            .accept_language
+            .as_ref()
+            .map_or(DEFAULT_ACCEPT_LANGUAGE, String::as_str);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_str(user_agent).context(ErrorKind::InvalidConfig)?,
+        );
+        headers.insert(
+            ACCEPT_LANGUAGE,
+            HeaderValue::from_str(accept_language).context(ErrorKind::InvalidConfig)?,
+        );
+
+        let mut builder = ClientBuilder::new()
+            .redirect(redirects)
+            .default_headers(headers)
+            .gzip(config.gzip.unwrap_or(true))
+            .timeout(timeout);
+
+        if let Some(ref proxy) = config.proxy {
+            builder = builder.proxy(Proxy::all(proxy.as_str()).context(ErrorKind::InvalidConfig)?);
+        }
+
+        Ok(Http {
+            client: builder.build().context(ErrorKind::InvalidConfig)?,
+            policy,
+        })
+    }
+}
+
+/// Decides which URLs may be fetched so users can not make
+/// the bot access services which are only reachable from its network.
+///
+/// Every address of a host has to be public unless it is in the allowlist.
+/// Hosts are resolved again when connecting, so a DNS server which answers
+/// differently within a few milliseconds is not covered.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    networks: Vec<(IpAddr, u8)>,
+    hosts: Vec<String>,
+    max_redirects: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            networks: Vec::new(),
+            hosts: Vec::new(),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+        }
+    }
+}
+
+impl Policy {
+    /// Parses a comma separated allowlist of host names,
+    /// addresses and networks like `10.1.0.0/16`.
+    pub fn with_allowlist(allowlist: &str) -> Result<Self, String> {
+        let mut policy = Policy::default();
+
+        for entry in allowlist
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let (addr, prefix) = match entry.find('/') {
+                Some(idx) => (&entry[..idx], Some(&entry[idx + 1..])),
+                None => (entry, None),
+            };
+
+            match (addr.parse::<IpAddr>(), prefix) {
+                (Ok(ip), prefix) => {
+                    let max = if ip.is_ipv4() { 32 } else { 128 };
+                    let prefix = match prefix {
+                        Some(prefix) => prefix
+                            .parse::<u8>()
+                            .ok()
+                            .filter(|&p| p <= max)
+                            .ok_or_else(|| format!("invalid prefix length in \"{}\"", entry))?,
+                        None => max,
+                    };
+                    policy.networks.push((ip, prefix));
+                }
+                (Err(_), None) => policy.hosts.push(entry.to_lowercase()),
+                (Err(_), Some(_)) => return Err(format!("invalid network \"{}\"", entry)),
+            }
+        }
+
+        Ok(policy)
+    }
+
+    pub fn max_redirects(mut self, max: usize) -> Self {
+        self.max_redirects = max;
+        self
+    }
+
+    /// Checks the scheme, port and every address of the host.
+    pub fn check(&self, url: &reqwest::Url) -> Result<(), ErrorKind> {
+        if !ALLOWED_SCHEMES.contains(&url.scheme()) {
+            return Err(ErrorKind::Forbidden);
+        }
+
+        let port = url.port_or_known_default().ok_or(ErrorKind::Forbidden)?;
+        if !ALLOWED_PORTS.contains(&port) {
+            return Err(ErrorKind::Forbidden);
+        }
+
+        let addrs = match url.host().ok_or(ErrorKind::Forbidden)? {
+            Host::Domain(domain) => {
+                let domain = domain.trim_end_matches('.').to_lowercase();
+                if self.hosts.contains(&domain) {
+                    return Ok(());
+                }
+
+                (domain.as_str(), port)
+                    .to_socket_addrs()
+                    .map_err(|_| ErrorKind::Resolve)?
+                    .map(|addr| addr.ip())
+                    .collect::<Vec<_>>()
+            }
+            Host::Ipv4(ip) => vec![IpAddr::V4(ip)],
+            Host::Ipv6(ip) => vec![IpAddr::V6(ip)],
+        };
+
+        if addrs.is_empty() {
+            return Err(ErrorKind::Resolve);
+        }
+
+        if addrs.iter().all(|&ip| is_public(ip) || self.allows(ip)) {
+            Ok(())
+        } else {
+            Err(ErrorKind::Forbidden)
+        }
+    }
+
+    fn allows(&self, ip: IpAddr) -> bool {
+        self.networks
+            .iter()
+            .any(|&(network, prefix)| in_network(ip, network, prefix))
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::max_value()
+                .checked_shl(32 - u32::from(prefix))
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::max_value()
+                .checked_shl(128 - u32::from(prefix))
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Returns false for private, loopback, link-local, multicast and reserved addresses.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            // Mapped addresses reach IPv4 hosts
+            if let Some(v4) = ip.to_ipv4() {
+                return is_public_v4(v4);
+            }
+
+            let segments = ip.segments();
+            // NAT64 and 6to4 addresses embed the IPv4 address they reach
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let v4 = u32::from(segments[6]) << 16 | u32::from(segments[7]);
+                return is_public_v4(Ipv4Addr::from(v4));
+            }
+            if segments[0] == 0x2002 {
+                let v4 = u32::from(segments[1]) << 16 | u32::from(segments[2]);
+                return is_public_v4(Ipv4Addr::from(v4));
+            }
+
+            let first = segments[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Local-use NAT64 prefix, the networks behind it are unknown
+                || segments[..3] == [0x64, 0xff9b, 1]
+                // Unique local addresses
+                || first & 0xfe00 == 0xfc00
+                // Link-local addresses
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        // "This network"
+        || octets[0] == 0
+        // Shared address space used for carrier-grade NAT
+        || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+        // Reserved for future use
+        || octets[0] >= 240)
+}
+
+/// A downloaded file with the headers needed to interpret it.
+#[derive(Clone, Debug)]
+pub struct Download {
+    pub content_type: Option<String>,
+    pub bytes: Vec<u8>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Url<'a> {
     url: Cow<'a, str>,
     max_kib: Option<usize>,
-    timeout: Option<Duration>,
+    http: Option<&'a Http>,
+    body: Option<Cow<'a, str>>,
+    headers: Vec<(String, String)>,
 }
 
 impl<'a> From<String> for Url<'a> {
@@ -20,7 +310,9 @@ impl<'a> From<String> for Url<'a> {
         Url {
             url: Cow::from(url),
             max_kib: None,
-            timeout: None,
+            http: None,
+            body: None,
+            headers: Vec::new(),
         }
     }
 }
@@ -30,7 +322,9 @@ impl<'a> From<&'a str> for Url<'a> {
         Url {
             url: Cow::from(url),
             max_kib: None,
-            timeout: None,
+            http: None,
+            body: None,
+            headers: Vec::new(),
         }
     }
 }
@@ -41,8 +335,22 @@ impl<'a> Url<'a> {
         self
     }
 
-    pub fn timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = Some(timeout);
+    /// Uses the client of a bot instead of the default one
+    /// which only allows public addresses.
+    pub fn http(mut self, http: &'a Http) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    /// Sends a POST request with `body` instead of a GET request.
+    pub fn post<B: Into<Cow<'a, str>>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Adds a header to the request, invalid headers make it fail.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
         self
     }
 
@@ -52,34 +360,129 @@ impl<'a> Url<'a> {
     /// The error indicated either a failed download or
     /// that the limit set by max_kib() was reached.
     pub fn request(&self) -> Result<String, DownloadError> {
-        let client = if let Some(timeout) = self.timeout {
-            ClientBuilder::new().timeout(timeout).build().unwrap()
-        } else {
-            Client::new()
+        let download = self.download()?;
+
+        Ok(String::from_utf8_lossy(&download.bytes).into_owned())
+    }
+
+    /// Downloads the file without converting it.
+    ///
+    /// Fails the same way as [`request()`](#method.request).
+    pub fn download(&self) -> Result<Download, DownloadError> {
+        let response = self.open()?;
+        let content_type = response.content_type.clone();
+        let bytes = response.read_all()?;
+
+        Ok(Download {
+            content_type,
+            bytes,
+        })
+    }
+
+    /// Sends the request without reading the body,
+    /// so the headers can be checked before deciding how much to download.
+    pub fn open(&self) -> Result<Response, DownloadError> {
+        let http = self.http.unwrap_or(&DEFAULT_HTTP);
+        let url = reqwest::Url::parse(&self.url).context(ErrorKind::Connection)?;
+        http.policy.check(&url)?;
+
+        let mut request = match self.body {
+            Some(ref body) => http.client.post(url).body(body.to_string()),
+            None => http.client.get(url),
         };
 
-        let mut response = client
-            .get(self.url.as_ref())
-            .header(CONNECTION, HeaderValue::from_static("close"))
-            .header(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.5"))
-            .send()
-            .context(ErrorKind::Connection)?;
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).context(ErrorKind::InvalidHeader)?;
+            if is_fixed_header(&name) {
+                Err(ErrorKind::InvalidHeader)?;
+            }
+
+            let value = HeaderValue::from_str(value).context(ErrorKind::InvalidHeader)?;
+            request = request.header(name, value);
+        }
+
+        let response = request.send().context(ErrorKind::Connection)?;
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        Ok(Response {
+            url: response.url().to_string(),
+            content_type,
+            content_length: response.content_length(),
+            max_kib: self.max_kib,
+            response,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.url
+    }
+}
+
+/// Checks if the header is set by the client and can not be changed by requests.
+fn is_fixed_header(name: &HeaderName) -> bool {
+    *name == HOST || *name == CONTENT_LENGTH || *name == TRANSFER_ENCODING || *name == CONNECTION
+}
+
+/// A response whose headers were received but whose body was not read yet.
+#[derive(Debug)]
+pub struct Response {
+    /// The URL after following redirects
+    pub url: String,
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    max_kib: Option<usize>,
+    response: reqwest::Response,
+}
+
+impl Response {
+    /// Reads at most `limit` bytes, the rest of the body is never downloaded.
+    pub fn read_start(mut self, limit: usize) -> Result<Vec<u8>, DownloadError> {
+        let mut bytes = Vec::new();
+        (&mut self.response)
+            .take(limit as u64)
+            .read_to_end(&mut bytes)
+            .context(ErrorKind::Read)?;
+
+        Ok(bytes)
+    }
+
+    /// Reads the whole body and fails if it is larger than the limit set by max_kib().
+    pub fn read_all(self) -> Result<Vec<u8>, DownloadError> {
+        let mut bytes = Vec::new();
+        self.stream(|chunk| {
+            bytes.extend_from_slice(chunk);
+            true
+        })?;
+
+        Ok(bytes)
+    }
 
+    /// Passes the body to `callback` in chunks as they arrive.
+    /// Once the callback returns false the rest of the body is not downloaded.
+    ///
+    /// Fails if more than the limit set by max_kib() is read.
+    pub fn stream<F>(mut self, mut callback: F) -> Result<(), DownloadError>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
         // 100 kibibyte buffer
         let mut buf = [0; 100 * 1024];
         let mut written = 0;
-        let mut bytes = Vec::new();
 
-        // Read until we reach EOF or max_kib KiB
+        // Read until we reach EOF, max_kib KiB or the callback is done
         loop {
-            let len = match response.read(&mut buf) {
+            let len = match self.response.read(&mut buf) {
                 Ok(0) => break,
                 Ok(len) => len,
                 Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
                 Err(e) => Err(e).context(ErrorKind::Read)?,
             };
 
-            bytes.extend_from_slice(&buf[..len]);
             written += len;
 
             // Check if the file is too large to download
@@ -88,13 +491,121 @@ impl<'a> Url<'a> {
                     Err(ErrorKind::DownloadLimit)?;
                 }
             }
+
+            if !callback(&buf[..len]) {
+                break;
+            }
         }
 
-        Ok(String::from_utf8_lossy(&bytes).into_owned())
+        Ok(())
     }
+}
 
-    pub fn as_str(&self) -> &str {
-        &self.url
+/// Removes IRC formatting, colors and other control characters like CTCP delimiters.
+pub fn strip_control_codes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Colors are followed by up to two digits for the foreground
+            // and optionally a comma and up to two digits for the background
+            '\x03' => {
+                if skip_max(&mut chars, 2, |c| c.is_ascii_digit()) > 0 && chars.peek() == Some(&',')
+                {
+                    let mut background = chars.clone();
+                    background.next();
+                    if skip_max(&mut background, 2, |c| c.is_ascii_digit()) > 0 {
+                        chars = background;
+                    }
+                }
+            }
+            // Hex colors
+            '\x04' => {
+                skip_max(&mut chars, 6, |c| c.is_ascii_hexdigit());
+            }
+            c if c.is_control() => (),
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Skips up to `max` characters matching `predicate` and returns how many were skipped.
+fn skip_max<I, P>(chars: &mut Peekable<I>, max: usize, predicate: P) -> usize
+where
+    I: Iterator<Item = char>,
+    P: Fn(char) -> bool,
+{
+    let mut skipped = 0;
+    while skipped < max && chars.peek().map_or(false, |&c| predicate(c)) {
+        chars.next();
+        skipped += 1;
+    }
+
+    skipped
+}
+
+/// Escapes the wildcards of LIKE patterns with `\`.
+#[cfg(feature = "sql")]
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Splits long responses into pages so they do not flood IRC.
+///
+/// The remaining lines are kept per key, usually the nick of the user,
+/// until they are requested with `more_command`.
+#[derive(Debug)]
+pub struct Pager {
+    page_size: usize,
+    more_command: String,
+    pending: Mutex<HashMap<String, VecDeque<String>>>,
+}
+
+impl Pager {
+    pub fn new(page_size: usize, more_command: &str) -> Self {
+        Pager {
+            page_size,
+            more_command: more_command.to_owned(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces the lines waiting for `key` and returns the first page.
+    pub fn start<I: IntoIterator<Item = String>>(&self, key: &str, lines: I) -> Vec<String> {
+        let lines = lines.into_iter().collect::<VecDeque<_>>();
+        self.pending.lock().insert(key.to_lowercase(), lines);
+
+        self.next(key)
+    }
+
+    /// Returns the next page for `key` which is empty if nothing is left.
+    pub fn next(&self, key: &str) -> Vec<String> {
+        let key = key.to_lowercase();
+        let mut pending = self.pending.lock();
+        let lines = match pending.get_mut(&key) {
+            Some(lines) => lines,
+            None => return Vec::new(),
+        };
+
+        let len = lines.len().min(self.page_size);
+        let mut page = lines.drain(..len).collect::<Vec<_>>();
+
+        if lines.is_empty() {
+            pending.remove(&key);
+        } else {
+            page.push(format!(
+                "{} more lines, send \"{}\" to continue.",
+                lines.len(),
+                self.more_command
+            ));
+        }
+
+        page
     }
 }
 
@@ -116,5 +627,21 @@ pub mod error {
         /// Reached download limit error
         #[fail(display = "Reached download limit")]
         DownloadLimit,
+
+        /// Forbidden URL error
+        #[fail(display = "The URL points to a forbidden scheme, port or address")]
+        Forbidden,
+
+        /// Resolve error
+        #[fail(display = "Failed to resolve the host")]
+        Resolve,
+
+        /// Invalid config error
+        #[fail(display = "The HTTP config is invalid")]
+        InvalidConfig,
+
+        /// Invalid header error
+        #[fail(display = "A header is invalid or can not be set")]
+        InvalidHeader,
     }
 }